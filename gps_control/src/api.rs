@@ -1,10 +1,18 @@
-use actix_web::{Error, HttpResponse, Responder, get, post, web};
+use actix_web::{Error, HttpResponse, Responder, delete, get, post, web};
 use actix_files::NamedFile;
-use serde::Deserialize;
-use crate::gps_interface::gps_control::{GPSControl, GPSMode};
-use crate::web_socket::GPSWebSocketMonitor;
-use crate::settings::{Modes, SettingsMessage, SettingsHandler};
+use serde::{Deserialize, Serialize};
+use crate::error::ApiError;
+use crate::geodesy::coordinates::{Ecef, Llh};
+use crate::geodesy::csrs_ppp::PppSolution;
+use crate::geodesy::helmert::{self, Frame};
+use crate::gps_interface::gps_control::{GetControlStatus, GPSControl, GPSMode, GPS_DATA_DIR};
+use crate::shutdown::{ShutdownRequest, ShutdownSender};
+use crate::supervisor::{GetProcesses, GetProcessOutput, ProcessSupervisor};
+use crate::status::{self, Links, LinkState, StartTime, SystemStatus};
+use crate::web_socket::{GetDevices, GetGPSStatus, GetSky, GetSurvey, GetTime, GPSWebSocketMonitor};
+use crate::settings::{GetSettingsStatus, Modes, ProfileMessage, SavedSite, SettingsMessage, SettingsHandler, SiteMessage};
 use actix::prelude::*;
+use std::collections::BTreeMap;
 
 type WebData = web::Data<(Addr<GPSWebSocketMonitor>, Addr<GPSControl>, Addr<SettingsHandler>)>;
 
@@ -23,71 +31,315 @@ struct RTKClientConfig {
 }
 
 #[post("/start")]
-async fn start(data: WebData, info: web::Json<RTKClientConfig>) -> impl Responder {
+async fn start(data: WebData, info: web::Json<RTKClientConfig>) -> Result<HttpResponse, ApiError> {
     log::info!("Starting the GPS system.");
     let gps_control = &data.get_ref().1;
     
     let rtcm_mode = GPSMode::RtcmIn(info.username.clone(), info.password.clone(), info.server.clone(), info.mount_point.clone(), info.port);
-    let control_future = gps_control.send(rtcm_mode).await;
-    
-    match control_future {
-        Ok(_) => HttpResponse::Ok(),
-        Err(e) => {
-            log::error!("Failed to set the RTK input mode: {}", e);
-            HttpResponse::InternalServerError()
-        }
-    }
+    gps_control.send(rtcm_mode).await??;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Switch the system into a new mode, stopping the streams of the old mode and
+/// reconfiguring the receiver for the new one.
+#[post("/mode")]
+async fn set_mode(data: WebData, info: web::Json<Modes>) -> Result<HttpResponse, ApiError> {
+    log::info!("Handling set mode api command.");
+    let settings_manager = &data.get_ref().2;
+
+    settings_manager.send(SettingsMessage::SetMode(info.0)).await??;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+struct ProfileConfig {
+    name: String,
+    mode: Modes,
+}
+
+/// The profiles returned by the API, without their passwords.
+fn redacted_profiles(profiles: BTreeMap<String, Modes>) -> web::Json<BTreeMap<String, Modes>> {
+    web::Json(profiles.into_iter().map(|(name, mode)| (name, mode.redacted())).collect())
+}
+
+#[get("/profiles")]
+async fn get_profiles(data: WebData) -> Result<impl Responder, ApiError> {
+    log::info!("Handling get profiles api command.");
+    let settings_manager = &data.get_ref().2;
+    let profiles = settings_manager.send(ProfileMessage::GetProfiles()).await??;
+    Ok(redacted_profiles(profiles))
+}
+
+#[post("/profiles")]
+async fn save_profile(data: WebData, info: web::Json<ProfileConfig>) -> Result<impl Responder, ApiError> {
+    log::info!("Handling save profile api command.");
+    let settings_manager = &data.get_ref().2;
+    let profile = info.into_inner();
+    let profiles = settings_manager.send(ProfileMessage::SaveProfile(profile.name, profile.mode)).await??;
+    Ok(redacted_profiles(profiles))
+}
+
+#[delete("/profiles/{name}")]
+async fn delete_profile(data: WebData, name: web::Path<String>) -> Result<impl Responder, ApiError> {
+    log::info!("Handling delete profile api command.");
+    let settings_manager = &data.get_ref().2;
+    let profiles = settings_manager.send(ProfileMessage::DeleteProfile(name.into_inner())).await??;
+    Ok(redacted_profiles(profiles))
+}
+
+/// Switch the system into the mode stored in the named profile.
+#[post("/profiles/{name}/activate")]
+async fn activate_profile(data: WebData, name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    log::info!("Handling activate profile api command.");
+    let settings_manager = &data.get_ref().2;
+
+    settings_manager.send(SettingsMessage::ActivateProfile(name.into_inner())).await??;
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[get("/settings")]
-async fn get_settings(data: WebData) -> impl Responder { 
+async fn get_settings(data: WebData) -> Result<impl Responder, ApiError> { 
     log::info!("Handling get settings api command.");
     let settings_manager = &data.get_ref().2;
 
-    let message = SettingsMessage::GetSettings();
-    let settings_return_future = settings_manager.send(message).await;
-
-    let settings_return = settings_return_future.expect("Failed to get settings from settings manager");
-    web::Json(settings_return.expect("Received settings error from settings manager."))
+    let settings = settings_manager.send(SettingsMessage::GetSettings()).await??;
+    Ok(web::Json(settings.redacted()))
 }
 
 #[post("/settings")]
-async fn set_settings(data: WebData, info: web::Json<Modes>) -> impl Responder {
+async fn set_settings(data: WebData, info: web::Json<Modes>) -> Result<HttpResponse, ApiError> {
     log::info!("Handling set settings api command");
     let settings_manager = &data.get_ref().2;
     let settings = info.0;
 
-    let message = SettingsMessage::SetSettings(settings);
-    let settings_return_future = settings_manager.send(message).await;
-
-    let settings_return = settings_return_future.expect("Failed to set settings in settings manager.");
-    settings_return.expect("Received setting erro from settings");
-    
-    HttpResponse::Ok().finish()
+    settings_manager.send(SettingsMessage::SetSettings(settings)).await??;
+    Ok(HttpResponse::Ok().finish())
 }
 
-#[get("/shutdown")]
-pub async fn shutdown(data: WebData) -> Result<HttpResponse, Error> {//(processor: web::Data<Addr<Processor>>) -> Result<HttpResponse, Error> {
-    //processor.do_send(SetState::Shutdown);
-    log::info!("Shutting down the server.");
-    tokio::spawn( async move {
-        std::thread::sleep(std::time::Duration::from_secs(2));
-        log::info!("Shutting down.");
-        std::process::exit(0);
-    } );
+/// Health of the whole system: mode, receiver and gpsd connection, child processes, links, disk usage and uptime.
+#[get("/status")]
+async fn get_status(data: WebData, supervisor: web::Data<Addr<ProcessSupervisor>>, start_time: web::Data<StartTime>) -> Result<impl Responder, ApiError> {
+    let (socket_monitor, gps_control, settings_manager) = data.get_ref();
 
-    let gps_control = &data.get_ref().1;
-    let control_future = gps_control.send(GPSMode::Stopped).await;
-    match control_future {
-        Ok(_) => (),
-        Err(e) => {
-            log::error!("Failed to stop the GPS service cleanly: {}", e)
-        }
+    let settings = settings_manager.send(GetSettingsStatus).await?;
+    let gps = socket_monitor.send(GetGPSStatus).await?;
+    let control = gps_control.send(GetControlStatus).await?;
+    let processes = supervisor.send(GetProcesses).await?;
+    let disk = web::block(|| status::disk_usage(GPS_DATA_DIR)).await
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+
+    let links = Links {
+        ntrip: status::ntrip_link(settings.mode, processes.get("str2str").map(|process| &process.state)),
+        //The LoRa radio link isn't used by the control software yet.
+        lora: LinkState::Disabled,
     };
+    Ok(web::Json(SystemStatus {
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_seconds: start_time.0.elapsed().as_secs(),
+        settings: settings,
+        gps: gps,
+        control: control,
+        processes: processes,
+        links: links,
+        disk: disk,
+    }))
+}
+
+#[derive(Deserialize)]
+struct DeviceOptions {
+    device: Option<String>,
+}
+
+/// Satellites in view with their elevation, azimuth, SNR and whether they are used, plus the DOP values.
+/// ?device=<path> picks the receiver, otherwise the first one is returned.
+#[get("/sky")]
+async fn get_sky(data: WebData, options: web::Query<DeviceOptions>) -> Result<impl Responder, ApiError> {
+    let socket_monitor = &data.get_ref().0;
+    let device = options.into_inner().device;
+    let sky = socket_monitor.send(GetSky { device: device.clone() }).await?;
+    sky.map(web::Json).ok_or_else(|| ApiError::DeviceNotFound(device.unwrap_or_default()))
+}
+
+/// Receivers reported by gpsd, with their fix.
+#[get("/devices")]
+async fn get_devices(data: WebData) -> Result<impl Responder, ApiError> {
+    let socket_monitor = &data.get_ref().0;
+    Ok(web::Json(socket_monitor.send(GetDevices).await?))
+}
+
+/// PPS timing quality: pulses received and missed, and the offset and jitter of the system clock.
+#[get("/time")]
+async fn get_time(data: WebData) -> Result<impl Responder, ApiError> {
+    let socket_monitor = &data.get_ref().0;
+    Ok(web::Json(socket_monitor.send(GetTime).await?))
+}
+
+/// Progress of the base station survey-in: duration, observations, mean ECEF position, accuracy and whether it is done.
+#[get("/base/survey")]
+async fn get_survey(data: WebData) -> Result<impl Responder, ApiError> {
+    let socket_monitor = &data.get_ref().0;
+    socket_monitor.send(GetSurvey).await?.map(web::Json).ok_or(ApiError::NoSurvey)
+}
+
+#[derive(Deserialize)]
+struct SiteName {
+    name: String,
+}
+
+/// Save the mean position of the finished survey-in as a named site, so later base starts can use it as their fixed position.
+#[post("/base/survey/save")]
+async fn save_survey(data: WebData, info: web::Json<SiteName>) -> Result<impl Responder, ApiError> {
+    log::info!("Handling save survey api command.");
+    let (socket_monitor, _, settings_manager) = data.get_ref();
+    let survey = socket_monitor.send(GetSurvey).await?.ok_or(ApiError::NoSurvey)?;
+    if !survey.valid {
+        return Err(ApiError::SurveyIncomplete);
+    }
+    let sites = settings_manager.send(SiteMessage::SaveSite(info.into_inner().name, SavedSite::from_survey(&survey))).await??;
+    Ok(web::Json(sites))
+}
+
+#[get("/sites")]
+async fn get_sites(data: WebData) -> Result<impl Responder, ApiError> {
+    log::info!("Handling get sites api command.");
+    let settings_manager = &data.get_ref().2;
+    Ok(web::Json(settings_manager.send(SiteMessage::GetSites()).await??))
+}
+
+#[delete("/sites/{name}")]
+async fn delete_site(data: WebData, name: web::Path<String>) -> Result<impl Responder, ApiError> {
+    log::info!("Handling delete site api command.");
+    let settings_manager = &data.get_ref().2;
+    Ok(web::Json(settings_manager.send(SiteMessage::DeleteSite(name.into_inner())).await??))
+}
+
+#[derive(Deserialize)]
+struct TransformRequest {
+    from: Frame,
+    to: Frame,
+    /// ECEF position in m, observed at epoch (a decimal year such as 2024.5).
+    position: Ecef,
+    epoch: f64,
+    /// Velocity in the target frame in m per year, to move the position to target_epoch.
+    #[serde(default)]
+    velocity: Option<Ecef>,
+    #[serde(default)]
+    target_epoch: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct TransformedPosition {
+    frame: Frame,
+    epoch: f64,
+    position: Ecef,
+    /// The position as latitude, longitude and ellipsoidal height.
+    llh: Llh,
+}
+
+/// Transform an ECEF position between ITRF2014, ITRF2020, WGS84 and NAD83(CSRS), for example to turn a PPP result
+/// into the position the receiver's fixed mode wants.
+#[post("/geodesy/transform")]
+async fn transform_position(info: web::Json<TransformRequest>) -> Result<impl Responder, ApiError> {
+    let request = info.into_inner();
+    let position = helmert::transform(&request.position, request.from, request.to, request.epoch,
+                                      request.velocity.as_ref(), request.target_epoch);
+    Ok(web::Json(TransformedPosition {
+        frame: request.to,
+        epoch: request.target_epoch.filter(|_| request.velocity.is_some()).unwrap_or(request.epoch),
+        position: position,
+        llh: position.to_llh(),
+    }))
+}
+
+/// Sigma above which an imported PPP position is reported as poor, in m.
+const PPP_SIGMA_WARNING: f64 = 0.05;
+
+#[derive(Deserialize)]
+struct PppImport {
+    /// Name of the site to save.
+    name: String,
+    /// Contents of the CSRS-PPP .sum or .csv result file.
+    contents: String,
+    /// Frame and epoch of the coordinates, for result files that don't give them.
+    #[serde(default)]
+    frame: Option<Frame>,
+    #[serde(default)]
+    epoch: Option<f64>,
+    /// ITRF2020 velocity of the site in m per year, for ITRF results at an old epoch.
+    #[serde(default)]
+    velocity: Option<Ecef>,
+    /// Epoch to save the position at, now if not given.
+    #[serde(default)]
+    target_epoch: Option<f64>,
+    /// Largest acceptable sigma in m before a warning is given.
+    #[serde(default)]
+    max_sigma: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct ImportedSite {
+    sites: BTreeMap<String, SavedSite>,
+    warnings: Vec<String>,
+}
+
+/// Save the final position of an NRCan CSRS-PPP solution as a named site, transformed to ITRF2020 for the receiver's
+/// fixed mode.
+#[post("/sites/import")]
+async fn import_site(data: WebData, info: web::Json<PppImport>) -> Result<impl Responder, ApiError> {
+    log::info!("Handling import site api command.");
+    let settings_manager = &data.get_ref().2;
+    let import = info.into_inner();
+
+    let solution = PppSolution::parse(&import.contents).map_err(ApiError::InvalidImport)?;
+    let frame = import.frame.or(solution.frame)
+        .ok_or(ApiError::InvalidImport("the result file doesn't give its frame, it must be given with the import".to_string()))?;
+    let target_epoch = import.target_epoch.unwrap_or_else(helmert::current_epoch);
+    let position = solution.itrf2020_position(frame, import.epoch.or(solution.epoch), import.velocity.as_ref(), target_epoch)
+        .map_err(ApiError::InvalidImport)?;
+
+    let mut warnings = Vec::new();
+    let max_sigma = import.max_sigma.unwrap_or(PPP_SIGMA_WARNING);
+    if solution.max_sigma() > max_sigma {
+        let warning = format!("The largest sigma of the PPP solution, {:.3} m, is more than {:.3} m.", solution.max_sigma(), max_sigma);
+        log::warn!("{}", warning);
+        warnings.push(warning);
+    }
+
+    let site = SavedSite::from_ppp(&position, solution.accuracy());
+    let sites = settings_manager.send(SiteMessage::SaveSite(import.name, site)).await??;
+    Ok(web::Json(ImportedSite { sites: sites, warnings: warnings }))
+}
+
+/// State of the supervised processes (gpsd, str2str and gpsrinex).
+#[get("/processes")]
+async fn get_processes(supervisor: web::Data<Addr<ProcessSupervisor>>) -> Result<impl Responder, ApiError> {
+    Ok(web::Json(supervisor.send(GetProcesses).await?))
+}
+
+/// Recent stdout and stderr output of a supervised process.
+#[get("/processes/{name}/output")]
+async fn get_process_output(supervisor: web::Data<Addr<ProcessSupervisor>>, name: web::Path<String>) -> Result<impl Responder, ApiError> {
+    let name = name.into_inner();
+    let output = supervisor.send(GetProcessOutput { name: name.clone() }).await?;
+    output.map(web::Json).ok_or(ApiError::ProcessNotFound(name))
+}
+
+#[derive(Deserialize)]
+struct ShutdownOptions {
+    #[serde(default)]
+    power_off: bool,
+}
+
+/// Shut the system down, stopping the child processes and the web server. With ?power_off=true the host
+/// is powered off as well. A POST, so that following a link or a prefetch can't shut the system down.
+#[post("/shutdown")]
+pub async fn shutdown(shutdown_sender: web::Data<ShutdownSender>, options: web::Query<ShutdownOptions>) -> Result<HttpResponse, ApiError> {
+    log::info!("Shutting down the server.");
+    shutdown_sender.send(ShutdownRequest { power_off: options.power_off }).await
+        .map_err(|e| ApiError::Shutdown(e.to_string()))?;
 
     Ok(HttpResponse::Ok()
     .content_type("text/plain")
     .body("Shutting down!"))
 }
-
-
@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{header, Method};
+use actix_web::{HttpRequest, HttpResponse, Responder, post, web};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::settings::AuthSettings;
+
+/// Name of the cookie holding the session id after logging in.
+pub const SESSION_COOKIE: &str = "gps_control_session";
+
+/// Authentication state shared by the web server workers. When neither a password nor a token is set
+/// in the settings, every request is allowed.
+///
+/// Requests are authenticated with either a bearer token (Authorization: Bearer <token>) or the session
+/// cookie returned by /api/login. Mutating requests, /api/shutdown and the /data/ files always need to be
+/// authenticated, the read only API only when public_status is turned off.
+pub struct Authentication {
+    settings: AuthSettings,
+    sessions: Mutex<HashSet<String>>,
+}
+
+impl Authentication {
+    pub fn new(settings: AuthSettings) -> Self {
+        if settings.password.is_none() && settings.token.is_none() {
+            log::warn!("No API password or token set, the web API is not protected.");
+        }
+        Authentication {
+            settings: settings,
+            sessions: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.settings.password.is_some() || self.settings.token.is_some()
+    }
+
+    /// Check whether the request is allowed through. The router matches the percent-decoded path, so that is the one
+    /// checked here, rather than the raw path where /api/%73hutdown would slip past the /api/shutdown rule.
+    pub fn is_allowed(&self, req: &ServiceRequest) -> bool {
+        if !self.enabled() || !self.requires_authentication(req.method(), req.match_info().as_str()) {
+            return true;
+        }
+
+        let bearer_token = req.headers().get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if let (Some(bearer_token), Some(token)) = (bearer_token, &self.settings.token) {
+            if constant_time_eq(bearer_token.trim(), token) {
+                return true;
+            }
+        }
+
+        match req.cookie(SESSION_COOKIE) {
+            Some(cookie) => self.sessions.lock().map(|sessions| sessions.contains(cookie.value())).unwrap_or(false),
+            None => false,
+        }
+    }
+
+    fn requires_authentication(&self, method: &Method, path: &str) -> bool {
+        if path == "/api/login" {
+            return false;
+        }
+        if path == "/data" || path.starts_with("/data/") || path == "/api/shutdown" {
+            return true;
+        }
+        if method != Method::GET && method != Method::HEAD {
+            return true;
+        }
+        path.starts_with("/api") && !self.settings.public_status
+    }
+
+    /// Start a new session if the password (or token) matches, returning the session id.
+    fn login(&self, password: &str) -> Option<String> {
+        let password_matches = self.settings.password.as_deref().is_some_and(|p| constant_time_eq(password, p));
+        let token_matches = self.settings.token.as_deref().is_some_and(|t| constant_time_eq(password, t));
+        if !password_matches && !token_matches {
+            return None;
+        }
+
+        let session = Uuid::new_v4().to_string();
+        match self.sessions.lock() {
+            Ok(mut sessions) => {
+                sessions.insert(session.clone());
+                Some(session)
+            },
+            Err(_) => None,
+        }
+    }
+
+    fn logout(&self, session: &str) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.remove(session);
+        }
+    }
+}
+
+/// Compare two secrets without returning early on the first mismatched byte.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    password: String,
+}
+
+#[post("/login")]
+async fn login(auth: web::Data<Authentication>, info: web::Json<LoginRequest>) -> Result<HttpResponse, ApiError> {
+    let session = auth.login(&info.password).ok_or(ApiError::Unauthorized)?;
+    log::info!("API login succeeded.");
+    let cookie = Cookie::build(SESSION_COOKIE, session)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+    Ok(HttpResponse::Ok().cookie(cookie).finish())
+}
+
+#[post("/logout")]
+async fn logout(auth: web::Data<Authentication>, req: HttpRequest) -> impl Responder {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        auth.logout(cookie.value());
+    }
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    cookie.make_removal();
+    HttpResponse::Ok().cookie(cookie).finish()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn auth(public_status: bool) -> Authentication {
+        Authentication::new(AuthSettings { password: Some("password".to_string()), token: None, public_status: public_status })
+    }
+
+    #[test]
+    fn test_protected_paths () {
+        let auth = auth(true);
+        assert! (!auth.requires_authentication(&Method::GET, "/"));
+        assert! (!auth.requires_authentication(&Method::GET, "/api/settings"));
+        assert! (!auth.requires_authentication(&Method::POST, "/api/login"));
+        assert! (auth.requires_authentication(&Method::POST, "/api/settings"));
+        assert! (auth.requires_authentication(&Method::DELETE, "/api/profiles/home"));
+        assert! (auth.requires_authentication(&Method::POST, "/api/shutdown"));
+        assert! (auth.requires_authentication(&Method::GET, "/data"));
+        assert! (auth.requires_authentication(&Method::GET, "/data/raw_data.obs"));
+        assert! (!auth.requires_authentication(&Method::GET, "/database"));
+    }
+
+    #[test]
+    fn test_private_status () {
+        let auth = auth(false);
+        assert! (!auth.requires_authentication(&Method::GET, "/static/index.html"));
+        assert! (auth.requires_authentication(&Method::GET, "/api/settings"));
+        assert! (auth.requires_authentication(&Method::GET, "/api/subscribe"));
+    }
+
+    #[test]
+    fn test_encoded_paths () {
+        use actix_web::test::TestRequest;
+
+        let auth = auth(false);
+        for uri in ["/api/shutdown", "/api/%73hutdown", "/%61pi/shutdown", "/%61%70%69/settings", "/%64ata/raw_data.obs"] {
+            assert! (!auth.is_allowed(&TestRequest::get().uri(uri).to_srv_request()), "{} was allowed", uri);
+        }
+        assert! (auth.is_allowed(&TestRequest::get().uri("/%73tatic/index.html").to_srv_request()));
+    }
+
+    #[test]
+    fn test_login () {
+        let auth = auth(true);
+        assert! (auth.login("wrong").is_none());
+        let session = auth.login("password").unwrap();
+        assert! (auth.sessions.lock().unwrap().contains(&session));
+        auth.logout(&session);
+        assert! (!auth.sessions.lock().unwrap().contains(&session));
+    }
+}
@@ -0,0 +1,152 @@
+use actix::MailboxError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+
+use crate::settings::SettingsError;
+
+/// Errors returned by the REST API. They are sent to the caller as a JSON body with a machine readable
+/// code, a message and optional details (such as the invalid settings fields).
+#[derive(Debug)]
+pub enum ApiError {
+    /// An actor could not be reached or dropped the request.
+    Mailbox(MailboxError),
+    Settings(SettingsError),
+    /// The GPS control failed to apply a command.
+    Control(String),
+    /// The shutdown could not be started.
+    Shutdown(String),
+    ProcessNotFound(String),
+    DeviceNotFound(String),
+    /// No survey-in progress has been reported by the receiver.
+    NoSurvey,
+    /// The survey-in hasn't reached its duration and accuracy yet.
+    SurveyIncomplete,
+    /// An imported result file could not be used.
+    InvalidImport(String),
+    /// Any other failure handling the request.
+    Internal(String),
+    Unauthorized,
+}
+
+/// JSON body of an error response.
+#[derive(Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::Mailbox(_) => "mailbox_error",
+            ApiError::Settings(SettingsError::InvalidMode) => "invalid_mode",
+            ApiError::Settings(SettingsError::Storage(_)) => "storage_error",
+            ApiError::Settings(SettingsError::Validation(_)) => "validation_error",
+            ApiError::Settings(SettingsError::ProfileNotFound(_)) => "profile_not_found",
+            ApiError::Settings(SettingsError::SiteNotFound(_)) => "site_not_found",
+            ApiError::Settings(SettingsError::Apply(_)) => "control_error",
+            ApiError::Control(_) => "control_error",
+            ApiError::Shutdown(_) => "shutdown_error",
+            ApiError::ProcessNotFound(_) => "process_not_found",
+            ApiError::DeviceNotFound(_) => "device_not_found",
+            ApiError::NoSurvey => "no_survey",
+            ApiError::SurveyIncomplete => "survey_incomplete",
+            ApiError::InvalidImport(_) => "invalid_import",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::Unauthorized => "unauthorized",
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            ApiError::Settings(SettingsError::Validation(errors)) => serde_json::to_value(errors).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ApiError::Mailbox(e) => write! (f, "Internal message failed: {}", e),
+            ApiError::Settings(e) => write! (f, "{}", e),
+            ApiError::Control(e) => write! (f, "GPS control failed: {}", e),
+            ApiError::Shutdown(e) => write! (f, "Failed to shut down: {}", e),
+            ApiError::ProcessNotFound(name) => write! (f, "No process named {}.", name),
+            ApiError::DeviceNotFound(path) => write! (f, "No GPS device {}.", path),
+            ApiError::NoSurvey => write! (f, "No survey-in has been reported by the receiver."),
+            ApiError::SurveyIncomplete => write! (f, "The survey-in has not finished, its position is not valid yet."),
+            ApiError::InvalidImport(e) => write! (f, "Failed to import the result file: {}", e),
+            ApiError::Internal(e) => write! (f, "Internal error: {}", e),
+            ApiError::Unauthorized => write! (f, "Authentication required."),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Settings(SettingsError::InvalidMode) => StatusCode::BAD_REQUEST,
+            ApiError::Settings(SettingsError::Validation(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Settings(SettingsError::ProfileNotFound(_)) | ApiError::Settings(SettingsError::SiteNotFound(_)) => StatusCode::NOT_FOUND,
+            ApiError::ProcessNotFound(_) | ApiError::DeviceNotFound(_) | ApiError::NoSurvey => StatusCode::NOT_FOUND,
+            ApiError::SurveyIncomplete => StatusCode::CONFLICT,
+            ApiError::InvalidImport(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status_code().is_server_error() {
+            log::error!("API request failed: {}", self);
+        } else {
+            log::warn!("API request rejected: {}", self);
+        }
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            details: self.details(),
+        })
+    }
+}
+
+impl From<MailboxError> for ApiError {
+    fn from(e: MailboxError) -> Self {
+        ApiError::Mailbox(e)
+    }
+}
+
+impl From<SettingsError> for ApiError {
+    fn from(e: SettingsError) -> Self {
+        ApiError::Settings(e)
+    }
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for ApiError {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        ApiError::Control(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::settings::FieldError;
+
+    #[test]
+    fn test_validation_error () {
+        let error = ApiError::from(SettingsError::Validation(vec![FieldError { field: "port".to_string(), message: "must be greater than 0".to_string() }]));
+        assert_eq! (error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq! (error.code(), "validation_error");
+        assert_eq! (error.details().unwrap()[0]["field"], "port");
+    }
+}
@@ -0,0 +1,94 @@
+use serde::{Serialize, Deserialize};
+
+/// WGS84 ellipsoid semi-major axis in m and flattening.
+pub const WGS84_A: f64 = 6_378_137.0;
+pub const WGS84_F: f64 = 1.0 / 298.257_223_563;
+/// First eccentricity squared of the WGS84 ellipsoid.
+pub const WGS84_E2: f64 = WGS84_F * (2.0 - WGS84_F);
+
+/// Earth centred, earth fixed position, in m.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Ecef {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+/// Geodetic position on the WGS84 ellipsoid: latitude and longitude in degrees, ellipsoidal height in m.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Llh {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub height: f64,
+}
+
+/// Radius of curvature in the prime vertical at the given latitude, in m.
+fn prime_vertical_radius(sin_latitude: f64) -> f64 {
+    WGS84_A / (1.0 - WGS84_E2 * sin_latitude * sin_latitude).sqrt()
+}
+
+impl Llh {
+    pub fn to_ecef(self) -> Ecef {
+        let (sin_lat, cos_lat) = self.latitude.to_radians().sin_cos();
+        let (sin_lon, cos_lon) = self.longitude.to_radians().sin_cos();
+        let n = prime_vertical_radius(sin_lat);
+        Ecef {
+            x: (n + self.height) * cos_lat * cos_lon,
+            y: (n + self.height) * cos_lat * sin_lon,
+            z: (n * (1.0 - WGS84_E2) + self.height) * sin_lat,
+        }
+    }
+}
+
+impl Ecef {
+    /// Latitude, longitude and height of the position. The latitude is iterated from Bowring's starting
+    /// value, which converges to well under a mm in a few steps for anywhere near the earth's surface.
+    pub fn to_llh(self) -> Llh {
+        let p = self.x.hypot(self.y);
+        let longitude = self.y.atan2(self.x);
+        if p < 1e-9 {
+            //On the polar axis.
+            let b = WGS84_A * (1.0 - WGS84_F);
+            return Llh { latitude: 90f64.copysign(self.z), longitude: 0.0, height: self.z.abs() - b };
+        }
+
+        let mut latitude = (self.z / (p * (1.0 - WGS84_E2))).atan();
+        let mut height = 0.0;
+        for _ in 0..5 {
+            let (sin_lat, cos_lat) = latitude.sin_cos();
+            let n = prime_vertical_radius(sin_lat);
+            height = p / cos_lat - n;
+            latitude = (self.z / (p * (1.0 - WGS84_E2 * n / (n + height)))).atan();
+        }
+        Llh { latitude: latitude.to_degrees(), longitude: longitude.to_degrees(), height: height }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_llh_to_ecef () {
+        let ecef = Llh { latitude: 0.0, longitude: 0.0, height: 10.0 }.to_ecef();
+        assert! ((ecef.x - 6_378_147.0).abs() < 1e-6 && ecef.y.abs() < 1e-6 && ecef.z.abs() < 1e-6);
+
+        let ecef = Llh { latitude: 45.0, longitude: -75.0, height: 100.0 }.to_ecef();
+        assert! ((ecef.x - 1_169_256.8587).abs() < 1e-4);
+        assert! ((ecef.y + 4_363_726.0038).abs() < 1e-4);
+        assert! ((ecef.z - 4_487_419.1195).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_ecef_to_llh () {
+        let llh = Ecef { x: -4_646_093.4773, y: 2_553_229.5358, z: -3_534_404.7109 }.to_llh();
+        assert! ((llh.latitude + 33.8688).abs() < 1e-9);
+        assert! ((llh.longitude - 151.2093).abs() < 1e-9);
+        assert! ((llh.height - 58.0).abs() < 1e-4);
+
+        let pole = Ecef { x: 0.0, y: 0.0, z: -6_356_752.3142 }.to_llh();
+        assert_eq! (pole.latitude, -90.0);
+        assert! (pole.height.abs() < 1e-4);
+    }
+}
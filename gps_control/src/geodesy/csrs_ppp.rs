@@ -0,0 +1,203 @@
+use super::coordinates::{Ecef, Llh};
+use super::helmert::{self, Frame};
+
+/// Longest time in years between the solution epoch and the epoch the site is used at before the velocity of the
+/// site is needed, plate motion being a few cm per year.
+const MAX_EPOCH_DIFFERENCE: f64 = 0.25;
+
+/// Final coordinates of an NRCan CSRS-PPP solution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PppSolution {
+    /// Frame and epoch (decimal year) of the coordinates, when the result file gives them.
+    pub frame: Option<Frame>,
+    pub epoch: Option<f64>,
+    pub position: Ecef,
+    /// 95% sigmas of the position in m, along X/Y/Z or north/east/up depending on the file.
+    pub sigma: [f64; 3],
+}
+
+impl PppSolution {
+    /// Largest of the sigmas, in m.
+    pub fn max_sigma(&self) -> f64 {
+        self.sigma.iter().cloned().fold(0.0, f64::max)
+    }
+
+    /// 3D accuracy of the position, in m.
+    pub fn accuracy(&self) -> f64 {
+        self.sigma.iter().map(|sigma| sigma * sigma).sum::<f64>().sqrt()
+    }
+
+    /// ITRF2020 position of the solution at target_epoch (a decimal year), the position the receiver works in.
+    ///  - frame, epoch: frame and epoch of the coordinates, from the result file or given with the import.
+    ///  - velocity: ITRF2020 velocity of the site in m per year, needed when the solution is in ITRF at an epoch
+    ///    more than MAX_EPOCH_DIFFERENCE away from target_epoch.
+    ///
+    /// NAD83(CSRS) coordinates are fixed to the North American plate, so they are the same at any epoch and the
+    /// plate motion is in the rates of the transformation, which is evaluated at target_epoch, not at the 2010.0
+    /// epoch the coordinates are given at.
+    pub fn itrf2020_position(&self, frame: Frame, epoch: Option<f64>, velocity: Option<&Ecef>, target_epoch: f64) -> Result<Ecef, String> {
+        if frame == Frame::Nad83Csrs {
+            return Ok(helmert::transform(&self.position, frame, Frame::Itrf2020, target_epoch, None, None));
+        }
+        let epoch = epoch.ok_or("the result file doesn't give its epoch, it must be given with the import")?;
+        if velocity.is_none() && (target_epoch - epoch).abs() > MAX_EPOCH_DIFFERENCE {
+            return Err(format!("the solution epoch {:.2} is {:.2} years from {:.2}, give the velocity of the site to move it, or a target_epoch",
+                               epoch, (target_epoch - epoch).abs(), target_epoch));
+        }
+        Ok(helmert::transform(&self.position, frame, Frame::Itrf2020, epoch, velocity, Some(target_epoch)))
+    }
+
+    /// Read either result file, telling them apart by the POS lines of the summary.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        if contents.lines().any(|line| line.trim_start().starts_with("POS ")) {
+            PppSolution::from_summary(contents)
+        } else {
+            PppSolution::from_csv(contents)
+        }
+    }
+
+    /// Read the ECEF coordinates from the POS lines of a .sum file, for example
+    /// "POS   X   ITRF20(2024:123)   918129.4312   918129.4550   0.0238   0.0042"
+    /// with the a priori value, the estimated value, their difference and the 95% sigma.
+    pub fn from_summary(contents: &str) -> Result<Self, String> {
+        let mut values: [Option<(f64, f64)>; 3] = [None; 3];
+        let mut reference = None;
+        for line in contents.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() < 3 || words[0] != "POS" {
+                continue;
+            }
+            let axis = match words[1] {
+                "X" => 0,
+                "Y" => 1,
+                "Z" => 2,
+                _ => continue,
+            };
+            let numbers: Vec<f64> = words[3..].iter().filter_map(|word| word.parse().ok()).collect();
+            if numbers.len() < 4 {
+                return Err(format!("POS {} line has {} values, expected 4", words[1], numbers.len()));
+            }
+            values[axis] = Some((numbers[1], numbers[3]));
+            reference = Some(words[2]);
+        }
+        let [x, y, z] = values;
+        let ((x, sigma_x), (y, sigma_y), (z, sigma_z)) = match (x, y, z) {
+            (Some(x), Some(y), Some(z)) => (x, y, z),
+            _ => return Err("the summary is missing the POS X, Y or Z line".to_string()),
+        };
+        let (frame, epoch) = reference.map(parse_reference).unwrap_or((None, None));
+        Ok(PppSolution {
+            frame: frame,
+            epoch: epoch,
+            position: Ecef { x: x, y: y, z: z },
+            sigma: [sigma_x, sigma_y, sigma_z],
+        })
+    }
+
+    /// Read the last row of a .csv file, which has latitude_decimal_degree, longitude_decimal_degree and
+    /// ellipsoidal_height_m columns and their latitude_sigma_m, longitude_sigma_m and ellipsoidal_height_sigma_m.
+    pub fn from_csv(contents: &str) -> Result<Self, String> {
+        let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+        let header: Vec<&str> = lines.next().ok_or("the file is empty")?.split(',').map(str::trim).collect();
+        let row: Vec<&str> = lines.next_back().ok_or("the file has no results")?.split(',').map(str::trim).collect();
+        let value = |name: &str| -> Result<f64, String> {
+            let column = header.iter().position(|column| *column == name).ok_or(format!("missing the {} column", name))?;
+            row.get(column).and_then(|value| value.parse().ok()).ok_or(format!("invalid {} value", name))
+        };
+        let llh = Llh {
+            latitude: value("latitude_decimal_degree")?,
+            longitude: value("longitude_decimal_degree")?,
+            height: value("ellipsoidal_height_m")?,
+        };
+        Ok(PppSolution {
+            frame: None,
+            epoch: None,
+            //The GRS80 ellipsoid of NAD83 differs from WGS84 by 0.1 mm in its semi-minor axis.
+            position: llh.to_ecef(),
+            sigma: [value("latitude_sigma_m")?, value("longitude_sigma_m")?, value("ellipsoidal_height_sigma_m")?],
+        })
+    }
+}
+
+/// Frame and epoch from the reference of a POS line, such as "ITRF20(2024:123)", "IGS14(2021.5)" or "NAD83(CSRS)".
+/// The epoch is either a decimal year or a year and day of year.
+fn parse_reference(reference: &str) -> (Option<Frame>, Option<f64>) {
+    let name = reference.to_ascii_uppercase();
+    let frame = if name.starts_with("NAD83") {
+        Some(Frame::Nad83Csrs)
+    } else if ["ITRF14", "ITRF2014", "IGS14", "IGB14"].iter().any(|prefix| name.starts_with(prefix)) {
+        Some(Frame::Itrf2014)
+    } else if ["ITRF20", "IGS20", "IGB20"].iter().any(|prefix| name.starts_with(prefix)) {
+        Some(Frame::Itrf2020)
+    } else {
+        None
+    };
+    let epoch = name.rsplit('(').next().and_then(|epoch| epoch.strip_suffix(')')).and_then(|epoch| {
+        match epoch.split_once(':') {
+            Some((year, day)) => Some(year.parse::<f64>().ok()? + (day.parse::<f64>().ok()? - 1.0) / 365.25),
+            None => epoch.parse().ok(),
+        }
+    });
+    (frame, epoch)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_summary () {
+        let summary = "3.3 ESTIMATED POSITION\n\
+                       POS   X   ITRF20(2024:123)    918129.4312    918129.4550    0.0238    0.0042\n\
+                       POS   Y   ITRF20(2024:123)  -4346071.2811  -4346071.2567    0.0244    0.0091\n\
+                       POS   Z   ITRF20(2024:123)   4561977.8150   4561977.8271    0.0121    0.0087\n";
+        let solution = PppSolution::parse(summary).unwrap();
+        assert_eq! (solution.frame, Some(Frame::Itrf2020));
+        assert! ((solution.epoch.unwrap() - 2024.3340).abs() < 1e-3);
+        assert_eq! (solution.position, Ecef { x: 918129.4550, y: -4346071.2567, z: 4561977.8271 });
+        assert_eq! (solution.max_sigma(), 0.0091);
+
+        assert! (PppSolution::parse("POS   X   ITRF20(2024:123)    918129.4312\n").is_err());
+        assert_eq! (parse_reference("NAD83(CSRS)(2010.0)"), (Some(Frame::Nad83Csrs), Some(2010.0)));
+        assert_eq! (parse_reference("ITRF2014"), (Some(Frame::Itrf2014), None));
+    }
+
+    #[test]
+    fn test_itrf2020_position () {
+        let solution = PppSolution {
+            frame: Some(Frame::Nad83Csrs),
+            epoch: Some(2010.0),
+            position: Ecef { x: 918_128.4, y: -4_346_072.8, z: 4_561_977.4 },
+            sigma: [0.01; 3],
+        };
+        let distance = |a: &Ecef, b: &Ecef| ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt();
+
+        //NAD83(CSRS) is transformed at the epoch the site is used at, the plate having moved about 2 cm a year at ALGO since 2010.
+        let now = solution.itrf2020_position(Frame::Nad83Csrs, Some(2010.0), None, 2025.0).unwrap();
+        let at_2010 = solution.itrf2020_position(Frame::Nad83Csrs, Some(2010.0), None, 2010.0).unwrap();
+        assert_eq! (now, helmert::transform(&solution.position, Frame::Nad83Csrs, Frame::Itrf2020, 2025.0, None, None));
+        assert! (distance(&now, &at_2010) > 0.25 && distance(&now, &at_2010) < 0.35);
+
+        //ITRF solutions at an old epoch need the velocity of the site.
+        assert! (solution.itrf2020_position(Frame::Itrf2020, Some(2020.0), None, 2025.0).is_err());
+        assert! (solution.itrf2020_position(Frame::Itrf2020, None, None, 2025.0).is_err());
+        assert_eq! (solution.itrf2020_position(Frame::Itrf2020, Some(2024.9), None, 2025.0).unwrap(), solution.position);
+        let velocity = Ecef { x: -0.016, y: -0.004, z: 0.004 };
+        let moved = solution.itrf2020_position(Frame::Itrf2020, Some(2020.0), Some(&velocity), 2025.0).unwrap();
+        assert! ((moved.x - (solution.position.x - 0.08)).abs() < 1e-6 && (moved.z - (solution.position.z + 0.02)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_csv () {
+        let csv = "latitude_decimal_degree,longitude_decimal_degree,ellipsoidal_height_m,latitude_sigma_m,longitude_sigma_m,ellipsoidal_height_sigma_m\n\
+                   45.0,-75.0,99.0,0.5,0.5,0.9\n\
+                   45.0,-75.0,100.0,0.012,0.010,0.025\n";
+        let solution = PppSolution::parse(csv).unwrap();
+        assert! ((solution.position.x - 1_169_256.8587).abs() < 1e-4);
+        assert! ((solution.accuracy() - 0.029_478).abs() < 1e-6);
+        assert_eq! (solution.frame, None);
+
+        assert! (PppSolution::parse("latitude_decimal_degree\n45.0\n").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use serde::{Serialize, Deserialize};
+
+use super::coordinates::Ecef;
+
+/// Milliarcseconds to radians.
+const MAS: f64 = std::f64::consts::PI / (180.0 * 3600.0 * 1000.0);
+/// Parts per billion.
+const PPB: f64 = 1e-9;
+
+/// Reference frames positions can be transformed between.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Frame {
+    #[serde(rename = "ITRF2014")]
+    Itrf2014,
+    #[serde(rename = "ITRF2020")]
+    Itrf2020,
+    /// WGS84 (G2139) is aligned with ITRF2020 at the cm level, so it is treated as ITRF2020.
+    #[serde(rename = "WGS84")]
+    Wgs84,
+    #[serde(rename = "NAD83(CSRS)")]
+    Nad83Csrs,
+}
+
+/// 14 parameter Helmert transformation, in the IERS convention: translations in m, rotations in mas and scale in
+/// ppb, the rate of each per year, and the epoch (decimal year) the parameters are given at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Helmert {
+    pub translation: [f64; 3],
+    pub rotation: [f64; 3],
+    pub scale: f64,
+    pub translation_rate: [f64; 3],
+    pub rotation_rate: [f64; 3],
+    pub scale_rate: f64,
+    pub epoch: f64,
+}
+
+/// ITRF2020 to ITRF2014, from the IERS ITRF2020 release.
+const ITRF2020_TO_ITRF2014: Helmert = Helmert {
+    translation: [-0.0014, -0.0009, 0.0014],
+    rotation: [0.0, 0.0, 0.0],
+    scale: -0.42,
+    translation_rate: [0.0, -0.0001, 0.0002],
+    rotation_rate: [0.0, 0.0, 0.0],
+    scale_rate: 0.0,
+    epoch: 2015.0,
+};
+
+/// ITRF2014 to NAD83(CSRS), as published by NRCan.
+const ITRF2014_TO_NAD83_CSRS: Helmert = Helmert {
+    translation: [1.0053, -1.90921, -0.54157],
+    rotation: [-26.78138, 0.42027, -10.93206],
+    scale: 0.37002,
+    translation_rate: [0.00079, -0.0006, -0.00144],
+    rotation_rate: [-0.06667, 0.75744, 0.05133],
+    scale_rate: -0.07201,
+    epoch: 2010.0,
+};
+
+impl Helmert {
+    /// The transformation in the opposite direction. The parameters are small enough for negating them to be
+    /// exact to well under a mm.
+    pub fn inverse(&self) -> Helmert {
+        let negate = |values: [f64; 3]| values.map(|value| -value);
+        Helmert {
+            translation: negate(self.translation),
+            rotation: negate(self.rotation),
+            scale: -self.scale,
+            translation_rate: negate(self.translation_rate),
+            rotation_rate: negate(self.rotation_rate),
+            scale_rate: -self.scale_rate,
+            epoch: self.epoch,
+        }
+    }
+
+    /// Transform a position observed at the given epoch, with the parameters propagated to that epoch.
+    pub fn apply(&self, position: &Ecef, epoch: f64) -> Ecef {
+        let dt = epoch - self.epoch;
+        let t: Vec<f64> = (0..3).map(|i| self.translation[i] + self.translation_rate[i] * dt).collect();
+        let r: Vec<f64> = (0..3).map(|i| (self.rotation[i] + self.rotation_rate[i] * dt) * MAS).collect();
+        let d = (self.scale + self.scale_rate * dt) * PPB;
+        let (x, y, z) = (position.x, position.y, position.z);
+        Ecef {
+            x: x + t[0] + d * x - r[2] * y + r[1] * z,
+            y: y + t[1] + r[2] * x + d * y - r[0] * z,
+            z: z + t[2] - r[1] * x + r[0] * y + d * z,
+        }
+    }
+}
+
+impl Frame {
+    /// Transformation from the frame to ITRF2014, which every transformation goes through.
+    fn to_itrf2014(self) -> Option<Helmert> {
+        match self {
+            Frame::Itrf2014 => None,
+            Frame::Itrf2020 | Frame::Wgs84 => Some(ITRF2020_TO_ITRF2014),
+            Frame::Nad83Csrs => Some(ITRF2014_TO_NAD83_CSRS.inverse()),
+        }
+    }
+}
+
+/// Transform a position between frames.
+///  - position: ECEF position in the from frame at the given epoch (decimal year).
+///  - velocity: velocity of the point in the to frame, in m per year. NAD83(CSRS) positions are normally given at
+///    epoch 2010.0, so the position is moved from the observation epoch to the target epoch with it.
+///  - target_epoch: epoch of the returned position, the observation epoch if not given.
+pub fn transform(position: &Ecef, from: Frame, to: Frame, epoch: f64, velocity: Option<&Ecef>, target_epoch: Option<f64>) -> Ecef {
+    let mut position = *position;
+    if let Some(helmert) = from.to_itrf2014() {
+        position = helmert.apply(&position, epoch);
+    }
+    if let Some(helmert) = to.to_itrf2014() {
+        position = helmert.inverse().apply(&position, epoch);
+    }
+    if let (Some(velocity), Some(target_epoch)) = (velocity, target_epoch) {
+        let dt = target_epoch - epoch;
+        position = Ecef { x: position.x + velocity.x * dt, y: position.y + velocity.y * dt, z: position.z + velocity.z * dt };
+    }
+    position
+}
+
+/// The current date as a decimal year.
+pub fn current_epoch() -> f64 {
+    let seconds = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+    1970.0 + seconds / (365.25 * 86400.0)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Roughly the position of the ALGO IGS station, in Algonquin Park.
+    const ALGO: Ecef = Ecef { x: 918_129.4, y: -4_346_071.3, z: 4_561_977.8 };
+
+    fn distance(a: &Ecef, b: &Ecef) -> f64 {
+        ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
+    }
+
+    #[test]
+    fn test_parameters_at_epoch () {
+        //At the parameter epoch only the translation moves a point at the origin.
+        let origin = Ecef { x: 0.0, y: 0.0, z: 0.0 };
+        let moved = ITRF2014_TO_NAD83_CSRS.apply(&origin, 2010.0);
+        assert! ((moved.x - 1.0053).abs() < 1e-9 && (moved.y + 1.90921).abs() < 1e-9);
+        let moved = ITRF2014_TO_NAD83_CSRS.apply(&origin, 2020.0);
+        assert! ((moved.x - 1.0132).abs() < 1e-9 && (moved.z + 0.55597).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_round_trip () {
+        for from in [Frame::Itrf2014, Frame::Itrf2020, Frame::Nad83Csrs] {
+            for to in [Frame::Itrf2014, Frame::Itrf2020, Frame::Nad83Csrs] {
+                let there = transform(&ALGO, from, to, 2023.5, None, None);
+                let back = transform(&there, to, from, 2023.5, None, None);
+                assert! (distance(&back, &ALGO) < 1e-4);
+            }
+        }
+        assert_eq! (transform(&ALGO, Frame::Wgs84, Frame::Itrf2020, 2023.5, None, None), ALGO);
+    }
+
+    #[test]
+    fn test_nad83_offset () {
+        //NAD83(CSRS) is offset from ITRF by 1 to 2 m across Canada.
+        let nad83 = transform(&ALGO, Frame::Itrf2014, Frame::Nad83Csrs, 2010.0, None, None);
+        let offset = distance(&nad83, &ALGO);
+        assert! (offset > 1.0 && offset < 2.5);
+
+        //ITRF2020 and ITRF2014 agree to within a few mm.
+        let itrf2020 = transform(&ALGO, Frame::Itrf2014, Frame::Itrf2020, 2020.0, None, None);
+        assert! (distance(&itrf2020, &ALGO) < 0.01);
+
+        let velocity = Ecef { x: -0.01, y: 0.0, z: 0.002 };
+        let moved = transform(&ALGO, Frame::Itrf2014, Frame::Itrf2014, 2020.0, Some(&velocity), Some(2010.0));
+        assert! ((moved.x - (ALGO.x + 0.1)).abs() < 1e-6 && (moved.z - (ALGO.z - 0.02)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod coordinates;
+pub mod csrs_ppp;
+pub mod helmert;
+pub mod projection;
@@ -0,0 +1,127 @@
+use serde::Serialize;
+
+use super::coordinates::{Ecef, Llh, WGS84_A, WGS84_F};
+
+/// Scale factor on the central meridian of the UTM zones.
+const UTM_SCALE: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+/// False northing of the southern hemisphere.
+const UTM_FALSE_NORTHING: f64 = 10_000_000.0;
+
+/// Universal Transverse Mercator coordinates in m.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Utm {
+    pub zone: u8,
+    /// Northern hemisphere, otherwise the northing includes the southern false northing.
+    pub north: bool,
+    pub easting: f64,
+    pub northing: f64,
+}
+
+/// Local east, north and up offsets from an origin, in m.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Enu {
+    pub east: f64,
+    pub north: f64,
+    pub up: f64,
+}
+
+/// Standard 6 degree UTM zone (1 to 60) of a longitude.
+pub fn utm_zone(longitude: f64) -> u8 {
+    (((longitude + 180.0) / 6.0).floor() as i32).rem_euclid(60) as u8 + 1
+}
+
+/// Project a position into UTM, in the zone of the position or the given zone. Uses Krüger's series to the
+/// 6th order in the third flattening, which is accurate to well under a mm within the zone.
+pub fn to_utm(position: &Llh, zone: Option<u8>) -> Utm {
+    let zone = zone.unwrap_or_else(|| utm_zone(position.longitude));
+    let central_meridian = zone as f64 * 6.0 - 183.0;
+
+    let n = WGS84_F / (2.0 - WGS84_F);
+    let (n2, n3, n4, n5, n6) = (n.powi(2), n.powi(3), n.powi(4), n.powi(5), n.powi(6));
+    let rectifying_radius = WGS84_A / (1.0 + n) * (1.0 + n2 / 4.0 + n4 / 64.0 + n6 / 256.0);
+    let alpha = [
+        n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0 + 41.0 * n4 / 180.0 - 127.0 * n5 / 288.0 + 7891.0 * n6 / 37800.0,
+        13.0 * n2 / 48.0 - 3.0 * n3 / 5.0 + 557.0 * n4 / 1440.0 + 281.0 * n5 / 630.0 - 1983433.0 * n6 / 1935360.0,
+        61.0 * n3 / 240.0 - 103.0 * n4 / 140.0 + 15061.0 * n5 / 26880.0 + 167603.0 * n6 / 181440.0,
+        49561.0 * n4 / 161280.0 - 179.0 * n5 / 168.0 + 6601661.0 * n6 / 7257600.0,
+        34729.0 * n5 / 80640.0 - 3418889.0 * n6 / 1995840.0,
+        212378941.0 * n6 / 319334400.0,
+    ];
+
+    let latitude = position.latitude.to_radians();
+    let longitude = (position.longitude - central_meridian).to_radians();
+    let eccentricity = 2.0 * n.sqrt() / (1.0 + n);
+    let t = (latitude.sin().atanh() - eccentricity * (eccentricity * latitude.sin()).atanh()).sinh();
+    let xi_prime = t.atan2(longitude.cos());
+    let eta_prime = (longitude.sin() / (1.0 + t * t).sqrt()).atanh();
+
+    let (mut xi, mut eta) = (xi_prime, eta_prime);
+    for (j, alpha) in alpha.iter().enumerate() {
+        let k = 2.0 * (j + 1) as f64;
+        xi += alpha * (k * xi_prime).sin() * (k * eta_prime).cosh();
+        eta += alpha * (k * xi_prime).cos() * (k * eta_prime).sinh();
+    }
+
+    let north = position.latitude >= 0.0;
+    Utm {
+        zone: zone,
+        north: north,
+        easting: UTM_FALSE_EASTING + UTM_SCALE * rectifying_radius * eta,
+        northing: UTM_SCALE * rectifying_radius * xi + if north { 0.0 } else { UTM_FALSE_NORTHING },
+    }
+}
+
+/// Offsets of a position from the origin, along the east, north and up directions at the origin.
+pub fn to_enu(position: &Ecef, origin: &Ecef) -> Enu {
+    let llh = origin.to_llh();
+    let (sin_lat, cos_lat) = llh.latitude.to_radians().sin_cos();
+    let (sin_lon, cos_lon) = llh.longitude.to_radians().sin_cos();
+    let (dx, dy, dz) = (position.x - origin.x, position.y - origin.y, position.z - origin.z);
+    Enu {
+        east: -sin_lon * dx + cos_lon * dy,
+        north: -sin_lat * cos_lon * dx - sin_lat * sin_lon * dy + cos_lat * dz,
+        up: cos_lat * cos_lon * dx + cos_lat * sin_lon * dy + sin_lat * dz,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn llh(latitude: f64, longitude: f64) -> Llh {
+        Llh { latitude: latitude, longitude: longitude, height: 0.0 }
+    }
+
+    #[test]
+    fn test_utm () {
+        let utm = to_utm(&llh(45.4215, -75.6972), None);
+        assert_eq! ((utm.zone, utm.north), (18, true));
+        assert! ((utm.easting - 445_454.3720).abs() < 1e-3 && (utm.northing - 5_030_011.8344).abs() < 1e-3);
+
+        let utm = to_utm(&llh(-33.8688, 151.2093), None);
+        assert_eq! ((utm.zone, utm.north), (56, false));
+        assert! ((utm.easting - 334_368.6336).abs() < 1e-3 && (utm.northing - 6_250_948.3454).abs() < 1e-3);
+
+        //On the central meridian of a fixed zone, and on the equator.
+        let utm = to_utm(&llh(45.0, -75.0), Some(18));
+        assert! ((utm.easting - 500_000.0).abs() < 1e-6 && (utm.northing - 4_982_950.4002).abs() < 1e-3);
+        let utm = to_utm(&llh(0.0, 3.0), None);
+        assert! ((utm.easting - 500_000.0).abs() < 1e-6 && utm.northing.abs() < 1e-6);
+
+        assert_eq! (utm_zone(-180.0), 1);
+        assert_eq! (utm_zone(179.9), 60);
+        assert_eq! (utm_zone(180.0), 1);
+    }
+
+    #[test]
+    fn test_enu () {
+        let origin = Llh { latitude: 45.0, longitude: -75.0, height: 100.0 }.to_ecef();
+        let position = Llh { latitude: 45.001, longitude: -74.999, height: 105.0 }.to_ecef();
+        let enu = to_enu(&position, &origin);
+        assert! ((enu.east - 78.8468).abs() < 1e-4);
+        assert! ((enu.north - 111.1341).abs() < 1e-4);
+        assert! ((enu.up - 4.9985).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::ErrorKind;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+/// Magic number chrony checks in every sample ("SOCK").
+const SOCK_MAGIC: i32 = 0x534f434b;
+/// Size of chrony's struct sock_sample on 64 bit Linux.
+pub const SOCK_SAMPLE_SIZE: usize = 40;
+
+/// Leap second warning sent with the samples. gpsd doesn't pass on the receiver's leap second warning,
+/// so the PPS samples from gpsd are always sent with None, the serial source takes it from NAV-TIMELS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LeapIndicator {
+    None = 0,
+    /// A second is inserted at the end of the day.
+    Insert = 1,
+    /// A second is removed at the end of the day.
+    Delete = 2,
+}
+
+/// A time sample for chrony's SOCK refclock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChronySample {
+    /// System time of the measurement.
+    pub clock_sec: i64,
+    pub clock_usec: i64,
+    /// True time minus the system time, in seconds.
+    pub offset: f64,
+    /// The sample is from a PPS pulse, so chrony only uses the offset within the second.
+    pub pulse: bool,
+    pub leap: LeapIndicator,
+}
+
+impl ChronySample {
+    /// Sample from a time measured against the system clock: the system time (clock) at which the GNSS
+    /// time (real) applied.
+    pub fn new(clock_sec: i64, clock_nsec: i64, real_sec: i64, real_nsec: i64, pulse: bool, leap: LeapIndicator) -> Self {
+        ChronySample {
+            clock_sec: clock_sec,
+            clock_usec: clock_nsec / 1000,
+            offset: (real_sec - clock_sec) as f64 + (real_nsec - clock_nsec) as f64 * 1e-9,
+            pulse: pulse,
+            leap: leap,
+        }
+    }
+
+    /// Encode the sample as chrony's struct sock_sample, in native byte order:
+    /// struct timeval tv, double offset, int pulse, int leap, int _pad, int magic.
+    pub fn encode(&self) -> [u8; SOCK_SAMPLE_SIZE] {
+        let mut buffer = [0u8; SOCK_SAMPLE_SIZE];
+        buffer[0..8].copy_from_slice(&self.clock_sec.to_ne_bytes());
+        buffer[8..16].copy_from_slice(&self.clock_usec.to_ne_bytes());
+        buffer[16..24].copy_from_slice(&self.offset.to_ne_bytes());
+        buffer[24..28].copy_from_slice(&(self.pulse as i32).to_ne_bytes());
+        buffer[28..32].copy_from_slice(&(self.leap as i32).to_ne_bytes());
+        buffer[36..40].copy_from_slice(&SOCK_MAGIC.to_ne_bytes());
+        buffer
+    }
+}
+
+/// Sends time samples to chrony over the socket of a SOCK refclock, for example with
+/// `refclock SOCK /run/chrony.gps_control.sock refid PPS` in chrony.conf.
+/// chrony creates the socket, so samples are dropped (with a log message) while it isn't running.
+pub struct ChronySocket {
+    path: PathBuf,
+    socket: UnixDatagram,
+    connected: bool,
+}
+
+impl ChronySocket {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+        Ok(ChronySocket { path: path.to_path_buf(), socket: socket, connected: true })
+    }
+
+    pub fn send(&mut self, sample: &ChronySample) {
+        match self.socket.send_to(&sample.encode(), &self.path) {
+            Ok(_) => {
+                if !self.connected {
+                    log::info!("Sending time samples to chrony at {}.", self.path.display());
+                    self.connected = true;
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => log::warn!("chrony isn't keeping up, dropped a time sample."),
+            Err(e) => {
+                if self.connected {
+                    log::error!("Failed to send the time sample to chrony at {}: {}", self.path.display(), e);
+                    self.connected = false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_pulse_offset () {
+        let sample = ChronySample::new(1_700_000_000, 999_999_000, 1_700_000_001, 0, true, LeapIndicator::None);
+        assert_eq! (sample.clock_usec, 999_999);
+        assert! ((sample.offset - 1e-6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_send_sample () {
+        let path = std::env::temp_dir().join(format!("gps_control_chrony_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let chrony = UnixDatagram::bind(&path).unwrap();
+
+        let sample = ChronySample::new(1_700_000_000, 500_000_000, 1_700_000_000, 500_000_250, true, LeapIndicator::Insert);
+        let mut socket = ChronySocket::new(&path).unwrap();
+        socket.send(&sample);
+
+        let mut buffer = [0u8; 64];
+        let size = chrony.recv(&mut buffer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq! (size, SOCK_SAMPLE_SIZE);
+        assert_eq! (i64::from_ne_bytes(buffer[0..8].try_into().unwrap()), 1_700_000_000);
+        assert_eq! (i64::from_ne_bytes(buffer[8..16].try_into().unwrap()), 500_000);
+        assert! ((f64::from_ne_bytes(buffer[16..24].try_into().unwrap()) - 250e-9).abs() < 1e-12);
+        assert_eq! (i32::from_ne_bytes(buffer[24..28].try_into().unwrap()), 1);
+        assert_eq! (i32::from_ne_bytes(buffer[28..32].try_into().unwrap()), 1);
+        assert_eq! (i32::from_ne_bytes(buffer[36..40].try_into().unwrap()), SOCK_MAGIC);
+    }
+}
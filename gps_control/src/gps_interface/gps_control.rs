@@ -3,11 +3,27 @@ use actix::Message;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use port_scanner;
 use std::process::Command;
+use serde::Serialize;
 
-const GPS_BAUDRATE: &str = "115200";
+use crate::settings::{Modes, PositionSourceKind, REDACTED_PASSWORD};
+use crate::supervisor::{redact_credentials, ProcessSpec, ProcessSupervisor, RestartPolicy, StartProcess, StopAll, StopProcess};
+use crate::web_socket::{BasePositionEvent, GPSWebSocketMonitor, SurveyEvent};
+use super::serial::PortLock;
+use super::survey::SurveyStatus;
+use super::ubx::split_high_precision;
+use crate::geodesy::coordinates::{Ecef, Llh};
+
+pub const GPS_BAUDRATE: u32 = 115200;
 const UBLOX_VERSION: &str = "27.30";
 const UBX_ACK: &str = "UBX-ACK-ACK:";
+/// UBX class and id of NAV-SVIN, sent without a payload to poll the survey-in progress.
+const NAV_SVIN_POLL: &str = "0x01,0x3b";
+const SURVEY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
 pub const GPS_DATA_DIR: &str= "data/";
+/// Names of the processes run by the supervisor.
+const GPSD: &str = "gpsd";
+const NTRIP: &str = "str2str";
+const RINEX: &str = "gpsrinex";
 
 /// GPSControl message, set GPS to either base station mode or rover mode. This also updates the system state and also sends
 /// a state set message to the other device connected through LORA (putting it into the other state).
@@ -18,24 +34,142 @@ pub const GPS_DATA_DIR: &str= "data/";
 /// 
 /// Rover mode sends recieves RTCM correction messages from the LORA radio into the GPS through the serial port and
 /// also retransmits any data recieved on the serial port to a socket for reading by other systems.
-#[derive(Message, Debug)]
+#[derive(Message)]
 #[rtype(result="Result<(), Box<dyn std::error::Error + Send + Sync>>")]
 pub enum GPSMode {
-    Base(String /*username*/, String/*password*/, String/*server*/, String/*mount_point*/, u16/*port*/, u32 /*survey_dwell_time*/, u32/*survey_position_accuracy*/, Option<f64>/*fixed_ecef_x*/, Option<f64>/*fixed_ecef_y*/, Option<f64>/*fixed_ecef_z*/, Option<f64>/*fixed_ecef_accuracy*/),
+    Base(String /*username*/, String/*password*/, String/*server*/, String/*mount_point*/, u16/*port*/, u32 /*survey_dwell_time*/, u32/*survey_position_accuracy*/, Option<FixedPosition>),
     Standalone,
     RAW (String /*data_directory*/,  String /*filename*/, u32 /*interval*/, u32 /*number_of_collections*/),
     RtcmIn(String /*username*/, String/*password*/, String/*server*/, String/*mount_point*/, u16/*port*/),
     Stopped,
 }
 
+/// Debug output is logged, so the NTRIP passwords are left out.
+impl std::fmt::Debug for GPSMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GPSMode::Base(username, _, server, mount_point, port, survey_dwell_time, survey_position_accuracy, fixed_position) => {
+                f.debug_tuple("Base").field(username).field(&REDACTED_PASSWORD).field(server).field(mount_point).field(port)
+                    .field(survey_dwell_time).field(survey_position_accuracy)
+                    .field(fixed_position).finish()
+            },
+            GPSMode::Standalone => write!(f, "Standalone"),
+            GPSMode::RAW(data_directory, filename, interval, number_of_collections) => {
+                f.debug_tuple("RAW").field(data_directory).field(filename).field(interval).field(number_of_collections).finish()
+            },
+            GPSMode::RtcmIn(username, _, server, mount_point, port) => {
+                f.debug_tuple("RtcmIn").field(username).field(&REDACTED_PASSWORD).field(server).field(mount_point).field(port).finish()
+            },
+            GPSMode::Stopped => write!(f, "Stopped"),
+        }
+    }
+}
+
+/// Fixed base station position, in either ECEF or latitude/longitude/height, and its accuracy in m.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FixedPosition {
+    Ecef(Ecef, f64),
+    Llh(Llh, f64),
+}
+
+impl FixedPosition {
+    pub fn ecef(&self) -> Ecef {
+        match self {
+            FixedPosition::Ecef(ecef, _) => *ecef,
+            FixedPosition::Llh(llh, _) => llh.to_ecef(),
+        }
+    }
+
+    pub fn llh(&self) -> Llh {
+        match self {
+            FixedPosition::Ecef(ecef, _) => ecef.to_llh(),
+            FixedPosition::Llh(llh, _) => *llh,
+        }
+    }
+}
+
+/// Sent when the settings of the active mode change, so that the parts of the receiver configuration and the
+/// streams affected by the change are re-applied without switching the whole mode again. Saved sites are already
+/// resolved into their fixed position.
+#[derive(Message)]
+#[rtype(result="Result<(), Box<dyn std::error::Error + Send + Sync>>")]
+pub struct SettingsChanged {
+    pub old: Modes,
+    pub new: Modes,
+}
+
+/// Asks the GPS control for the state of the receiver, for /api/status.
+#[derive(Message)]
+#[rtype(result="ControlStatus")]
+pub struct GetControlStatus;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ControlStatus {
+    /// Serial device of the receiver and whether it is present.
+    pub receiver_device: String,
+    pub receiver_present: bool,
+}
+
+impl From<Modes> for GPSMode {
+    fn from(mode: Modes) -> Self {
+        //A saved site has already been resolved into the fixed position by the settings.
+        let fixed_position = mode.fixed_position();
+        match mode {
+            Modes::RTKRover{username, password, server, mount_point, port} => {
+                GPSMode::RtcmIn(username, password, server, mount_point, port)
+            },
+            Modes::RTKBase{username, password, server, mount_point, port, survey_dwell_time, survey_position_accuracy, ..} => {
+                GPSMode::Base(username, password, server, mount_point, port, survey_dwell_time, survey_position_accuracy, fixed_position)
+            },
+            Modes::PPPMode{data_directory, filename, interval, number_of_collections} => {
+                GPSMode::RAW(data_directory, filename, interval, number_of_collections)
+            },
+            Modes::Standalone => GPSMode::Standalone,
+        }
+    }
+}
+
+
+/// CFG-TMODE items setting the fixed base position: ECEF (POS_TYPE 0) in cm or latitude/longitude (POS_TYPE 1) in
+/// 1e-7 degrees and height in cm, each with its high precision part, and the accuracy in 0.1 mm.
+fn fixed_position_items(fixed_position: &FixedPosition) -> Vec<String> {
+    let item = |name: &str, value: i64| format!("CFG-TMODE-{},{}", name, value);
+    let mut items = Vec::new();
+    let accuracy = match fixed_position {
+        FixedPosition::Ecef(ecef, accuracy) => {
+            items.push(item("POS_TYPE", 0));
+            for (name, value) in [("ECEF_X", ecef.x), ("ECEF_Y", ecef.y), ("ECEF_Z", ecef.z)] {
+                let (standard, high_precision) = split_high_precision(value * 100.0);
+                items.push(item(name, standard));
+                items.push(item(&format!("{}_HP", name), high_precision));
+            }
+            accuracy
+        },
+        FixedPosition::Llh(llh, accuracy) => {
+            items.push(item("POS_TYPE", 1));
+            for (name, value) in [("LAT", llh.latitude * 1e7), ("LON", llh.longitude * 1e7), ("HEIGHT", llh.height * 100.0)] {
+                let (standard, high_precision) = split_high_precision(value);
+                items.push(item(name, standard));
+                items.push(item(&format!("{}_HP", name), high_precision));
+            }
+            accuracy
+        },
+    };
+    items.push(item("FIXED_POS_ACC", (accuracy * 1e4).round() as i64));
+    items
+}
 
 ///GPS control strucutre, used to set up the the gpsd server through a combination of command line gpsctl and ubxtool commands.
 pub struct GPSControl {
     ip_address: IpAddr,
     port: u16,
-    gpsd_command: Option<std::process::Child>,
-    ntrip_command: Option<std::process::Child>,
-    rinex_collection_command: Option<std::process::Child>,
+    source: PositionSourceKind,
+    supervisor: Addr<ProcessSupervisor>,
+    web_socket_monitor: Addr<GPSWebSocketMonitor>,
+    /// The base station is surveying in its position, so the progress is polled.
+    surveying: bool,
+    /// Takes the receiver port from the serial source while ubxtool runs on it.
+    port_lock: PortLock,
     gps_usb_port: String,
     io_port: String //u16,
 }
@@ -47,10 +181,16 @@ impl GPSControl {
     ///  - gps_usb_port: device/port name the gps usb is connected to
     ///  - io_port: local tcp port that NMEA is output on and RTCM input on in rover mode
     ///             or RTCM is output on in base station mode.
+    ///  - supervisor: process supervisor running gpsd, str2str and gpsrinex.
+    ///  - web_socket_monitor: where the survey-in progress and the base position are sent.
+    ///  - source: whether the receiver is read through gpsd, which is then started here, or directly.
     pub fn new (ip_address: Option<&str>, 
                 port: Option<u16>,
+                source: PositionSourceKind,
                 gps_usb_port: Option<String>,
-                io_port: Option<String>) -> Self {
+                io_port: Option<String>,
+                supervisor: Addr<ProcessSupervisor>,
+                web_socket_monitor: Addr<GPSWebSocketMonitor>) -> Self {
 
         let ip_address = match ip_address {
             Some(ip) => ip,
@@ -79,21 +219,31 @@ impl GPSControl {
         GPSControl {
             ip_address: ip_address,
             port: port,
-            gpsd_command: None,
-            ntrip_command: None,
-            rinex_collection_command: None,
+            source: source,
+            supervisor: supervisor,
+            web_socket_monitor: web_socket_monitor,
+            surveying: false,
+            port_lock: PortLock::default(),
             gps_usb_port: gps_usb_port,
             io_port: io_port
         }    
     }
 
+    /// Lock to give the serial source, so the receiver port is handed over to ubxtool when configuring the receiver.
+    pub fn port_lock(&self) -> PortLock {
+        self.port_lock.clone()
+    }
+
     ///Start collecting data in raw mode, saving the ubx data to a file.
     ///  - data_directory: directory to save the data to.
     ///  - filename: filename to save the rinex observations to.
     ///  - interval_in_s: number of seconds to wait between collections.
     ///  - duration_in_min: number of minutes to collect data for.
-    fn set_raw_mode(&mut self, data_directory: &str, filename: &str, interval_in_s: u32, number_of_collections: u32) { 
+    fn set_raw_mode(&mut self, data_directory: &str, filename: &str, interval_in_s: u32, number_of_collections: u32) -> std::io::Result<()> {
         log::info!("Setting GPS into raw binary mode.");
+        if self.source != PositionSourceKind::Gpsd {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "RINEX collection with gpsrinex needs gpsd"));
+        }
 
         let ubx_commands = vec![
                             ("-p", "RESET"),
@@ -107,27 +257,32 @@ impl GPSControl {
                             ("-e", "RAWX"),
                             ("-z", "CFG-MSGOUT-UBX_RXM_RAWX_USB,1")
                         ];
-        GPSControl::run_ubx_commands(ubx_commands);
-        
-        //Kill the current runner before restarting, if running.
-        if let Some(mut cmd) = self.rinex_collection_command.take() {
-            cmd.kill().expect("gpsrinex couldn't be killed!");
-        };
+        self.run_ubx_commands(ubx_commands)?;
 
+        self.start_rinex_collection(data_directory, filename, interval_in_s, number_of_collections)
+    }
+
+    /// (Re)start the gpsrinex process collecting RINEX observations. It exits once the collections are done,
+    /// so it's only restarted if it fails.
+    fn start_rinex_collection(&mut self, data_directory: &str, filename: &str, interval_in_s: u32, number_of_collections: u32) -> std::io::Result<()> {
         let filename = std::path::Path::new(data_directory).join(filename);
-        self.rinex_collection_command = Some(Command::new ("gpsrinex")
-                                .arg("-i").arg(interval_in_s.to_string())
-                                .arg("-n").arg(number_of_collections.to_string())
-                                .arg("-f").arg(filename)
-                                .spawn().expect("Failed to execute gps2rinex."));
-    
+        let args = vec!["-i".to_string(), interval_in_s.to_string(),
+                        "-n".to_string(), number_of_collections.to_string(),
+                        "-f".to_string(), filename.to_string_lossy().to_string()];
+        self.start_process(RINEX, "gpsrinex", args, RestartPolicy::OnFailure)
+    }
+
+    /// Have the supervisor start (or replace) a process. Fails if the supervisor can't take the request.
+    fn start_process(&self, name: &str, program: &str, args: Vec<String>, restart: RestartPolicy) -> std::io::Result<()> {
+        self.supervisor.try_send(StartProcess {
+            name: name.to_string(),
+            spec: ProcessSpec { program: program.to_string(), args: args, restart: restart },
+        }).map_err(|e| std::io::Error::other(format!("Failed to start {}: {}", name, e)))
     }
 
     /// Set the rover into base station mode, enabling appropriate RTCM outputs and position modes.
-    /// This is then followed by setting up the str2str process to stream those rtcm corrections.
-    fn set_base_station_mode(&mut self, username: &str, password: &str, server: &str, mount_point: &str, port: u16,
-                             survey_dwell_time: u32, survey_position_accuracy: u32, 
-                             fixed_ecef_x: Option<f64>, fixed_ecef_y: Option<f64>, fixed_ecef_z: Option<f64>, fixed_ecef_accuracy: Option<f64>) {
+    /// The str2str process streaming those rtcm corrections is started separately, with start_ntrip_caster.
+    fn set_base_station_mode(&mut self, survey_dwell_time: u32, survey_position_accuracy: u32, fixed_position: Option<FixedPosition>) -> std::io::Result<()> {
         log::info!("Setting the GPS into base station mode and setting the serial port TX to output RTCM messages.");
 
         let ubx_commands = vec![
@@ -143,91 +298,71 @@ impl GPSControl {
                             ("-z", "CFG-MSGOUT-RTCM_3X_TYPE1124_UART2,1"),
                             ("-z", "CFG-MSGOUT-RTCM_3X_TYPE1230_UART2,5"),
                         ];
-        GPSControl::run_ubx_commands(ubx_commands);
-
-        if fixed_ecef_x != None && fixed_ecef_y != None && fixed_ecef_z != None && fixed_ecef_accuracy != None {
-            let split_position = |val: f64| -> (i64, i64) {
-                let std_precision: i64 = val.floor() as i64;
-                let high_precision: i64 = ((val - val.floor()) * 100.0).floor() as i64;
-                (std_precision, high_precision)
-            };
-            log::info!("Setting up fixed mode base station.");
-
-            let (ecef_x_sp, ecef_x_hp) = split_position(fixed_ecef_x.unwrap());
-            let (ecef_y_sp, ecef_y_hp) = split_position(fixed_ecef_y.unwrap());
-            let (ecef_z_sp, ecef_z_hp) = split_position(fixed_ecef_z.unwrap());
-            let ecef_acc = fixed_ecef_accuracy.unwrap();
-
-            let str_ecef_x = "CFG-TMODE-ECEF_X,".to_owned() + &ecef_x_sp.to_string();
-            let str_ecef_x_hp = "CFG-TMODE-ECEF_X_HP,".to_owned() + &ecef_x_hp.to_string();
-            let str_ecef_y = "CFG-TMODE-ECEF_Y,".to_owned() + &ecef_y_sp.to_string();
-            let str_ecef_y_hp = "CFG-TMODE-ECEF_Y_HP,".to_owned() + &ecef_y_hp.to_string();
-            let str_ecef_z = "CFG-TMODE-ECEF_Z,".to_owned() + &ecef_z_sp.to_string();
-            let str_ecef_z_hp = "CFG-TMODE-ECEF_Z_HP,".to_owned() + &ecef_z_hp.to_string();
-            let str_ecef_acc = "CFG-TMODE-FIXED_POS_ACC,".to_owned() + &ecef_acc.to_string();         
-            
-            let ubx_commands = vec![
-                            ("-z", "CFG-TMODE-MODE,2"), //fixed base mode
-                            ("-z", "CFG-TMODE-POS_TYPE,0"), //ecef co-ordinates
-                            ("-z", &str_ecef_x),
-                            ("-z", &str_ecef_x_hp),
-                            ("-z", &str_ecef_y),
-                            ("-z", &str_ecef_y_hp),
-                            ("-z", &str_ecef_z),
-                            ("-z", &str_ecef_z_hp),
-                            ("-z", &str_ecef_acc),
-                        ];
-            GPSControl::run_ubx_commands(ubx_commands);
+        self.run_ubx_commands(ubx_commands)?;
+
+        self.set_base_time_mode(survey_dwell_time, survey_position_accuracy, fixed_position)
+    }
+
+    /// Configure the receiver time mode, either surveying in the base position or using the given fixed position.
+    fn set_base_time_mode(&mut self, survey_dwell_time: u32, survey_position_accuracy: u32, fixed_position: Option<FixedPosition>) -> std::io::Result<()> {
+        if let Some(fixed_position) = fixed_position {
+            let (ecef, llh) = (fixed_position.ecef(), fixed_position.llh());
+            log::info!("Setting up fixed mode base station at {:.9}, {:.9}, {:.4} m (ECEF {:.4}, {:.4}, {:.4} m).",
+                       llh.latitude, llh.longitude, llh.height, ecef.x, ecef.y, ecef.z);
+            self.surveying = false;
+            self.web_socket_monitor.do_send(BasePositionEvent { position: Some(ecef) });
+
+            let commands = fixed_position_items(&fixed_position);
+            let mut ubx_commands = vec![("-z", "CFG-TMODE-MODE,2")]; //fixed base mode
+            ubx_commands.extend(commands.iter().map(|command| ("-z", command.as_str())));
+            self.run_ubx_commands(ubx_commands)
         } else {
             log::info! ("Setting up survey in base station.");
             let min_dur_setting = "CFG-TMODE-SVIN_MIN_DUR,".to_owned() + &survey_dwell_time.to_string();
             let min_acc_setting = "CFG-TMODE-SVIN_ACC_LIMIT,".to_owned() + &survey_position_accuracy.to_string();
-            let ubx_commands = vec![
+            let mut ubx_commands = vec![
                             ("-z", "CFG-TMODE-MODE,1"), //Survey in mode
                             ("-z", &min_dur_setting),
                             ("-z", &min_acc_setting),
                         ];
-            GPSControl::run_ubx_commands(ubx_commands);
+            if self.source == PositionSourceKind::Serial {
+                //The serial position source reads the progress, rather than it being polled through gpsd.
+                ubx_commands.push(("-z", "CFG-MSGOUT-UBX_NAV_SVIN_USB,1"));
+            }
+            self.surveying = true;
+            //The base position is known again once the survey completes.
+            self.web_socket_monitor.do_send(BasePositionEvent { position: None });
+            self.run_ubx_commands(ubx_commands)
         }
+    }
 
-
+    /// (Re)start the str2str process streaming the base station RTCM corrections to the NTRIP caster.
+    fn start_ntrip_caster(&mut self, _username: &str, password: &str, server: &str, mount_point: &str, port: u16) -> std::io::Result<()> {
         log::info!("Starting the ntrip caster.");
         let address_out = "ntrips://:".to_string()+password+"@"+server+":"+&port.to_string()+"/"+&mount_point.to_string();
         let address_in = "serial://".to_string() + &self.io_port + ":115200:8:n:1:off"; //"tcpcli://127.0.0.1:".to_string()+&self.io_port.to_string();
 
-        log::info! ("Command: str2str -in {} -out {}", address_in, address_out);
-
-        //Kill the current runner before restarting, if running.
-        if let Some(mut cmd) = self.ntrip_command.take() {
-            cmd.kill().expect("str2str couldn't be killed!");
-        };
-
-        self.ntrip_command = Some(Command::new ("str2str").arg("-in").arg(address_in).arg("-out").arg(address_out).spawn().expect("Failed to execute str2str."));
+        log::info! ("Command: str2str -in {} -out {}", redact_credentials(&address_in), redact_credentials(&address_out));
+        self.start_process(NTRIP, "str2str", vec!["-in".to_string(), address_in, "-out".to_string(), address_out], RestartPolicy::Always)
     }
 
     /// Start the RTCM streaming to the local redirect server from the given NTRIP address.
-    fn set_rtcm_input_mode(&mut self, username: &str, password: &str, server: &str, mount_point: &str, port: u16) {
+    fn set_rtcm_input_mode(&mut self, username: &str, password: &str, server: &str, mount_point: &str, port: u16) -> std::io::Result<()> {
         
         log::info!("Setting the GPS to accept RTCM input.");
         let address_in = "ntrip://".to_string()+username+":"+password+"@"+server+":"+&port.to_string()+"/"+&mount_point.to_string();
         let address_out = "serial://".to_string() + &self.io_port + ":115200";//"tcpcli://127.0.0.1:".to_string()+&self.io_port.to_string();
 
-        log::info! ("Command: str2str -in {} -out {}", address_in, address_out);
-
-        //Kill the current runner before restarting, if running.
-        if let Some(mut cmd) = self.ntrip_command.take() {
-            cmd.kill().expect("str2str couldn't be killed!");
-        };
-
-        self.ntrip_command = Some(Command::new ("str2str").arg("-in").arg(address_in).arg("-out").arg(address_out).spawn().expect("Failed to execute str2str."));
+        log::info! ("Command: str2str -in {} -out {}", redact_credentials(&address_in), redact_credentials(&address_out));
+        self.start_process(NTRIP, "str2str", vec!["-in".to_string(), address_in, "-out".to_string(), address_out], RestartPolicy::Always)?;
         
         let ubx_commands = vec![
                             ("-z", "CFG-UART2INPROT-RTCM3X,1")
                         ];
-        GPSControl::run_ubx_commands(ubx_commands);
+        self.run_ubx_commands(ubx_commands)
     }
 
-    fn set_rover_mode(&self) {
+    fn set_rover_mode(&self) -> std::io::Result<()> {
         log::info!("Setting the GPS into rover mode, and the serial TX to send out NMEA data.");
 
         let ubx_commands = vec![
@@ -240,6 +375,7 @@ impl GPSControl {
                             ("-d", "SBAS"),
                             ("-e", "GPS"),
                             ("-d", "RAWX"),
+                            ("-z", "CFG-TMODE-MODE,0"), //disable any base station time mode
                             ("-z", "CFG-NMEA-HIGHPREC,1"),
                             ("-z", "CFG-NAVSPG-DYNMODEL,0"), //ship mode
                             ("-z", "CFG-UART2-ENABLED,1"),
@@ -249,12 +385,64 @@ impl GPSControl {
                             ("-z", "CFG-UART2OUTPROT-RTCM3X,0")
                             //("-z", "CFG-MSGOUT-UBX_RXM_RAWX_USB,1")
                         ];
-        GPSControl::run_ubx_commands(ubx_commands);
+        self.run_ubx_commands(ubx_commands)?;
+
+        if self.source == PositionSourceKind::Serial {
+            //The navigation solution is read from the UBX messages, for the full precision.
+            let ubx_commands = vec![
+                            ("-z", "CFG-USBOUTPROT-UBX,1"),
+                            ("-z", "CFG-MSGOUT-UBX_NAV_PVT_USB,1"),
+                            ("-z", "CFG-MSGOUT-UBX_NAV_HPPOSLLH_USB,1"),
+                            ("-z", "CFG-MSGOUT-UBX_NAV_STATUS_USB,1"),
+                            ("-z", "CFG-MSGOUT-UBX_NAV_TIMELS_USB,10"), //leap second warning for chrony
+                        ];
+            self.run_ubx_commands(ubx_commands)?;
+        }
+        Ok(())
+    }
+
+    /// Stop the NTRIP and RINEX collection processes belonging to the previous mode, if running.
+    fn stop_streams(&mut self) {
+        self.surveying = false;
+        self.web_socket_monitor.do_send(BasePositionEvent { position: None });
+        self.supervisor.do_send(StopProcess { name: NTRIP.to_string() });
+        self.supervisor.do_send(StopProcess { name: RINEX.to_string() });
+    }
+
+    /// Poll NAV-SVIN through gpsd while surveying in, passing the progress on to the web sockets.
+    fn poll_survey(&mut self, ctx: &mut Context<Self>) {
+        if !self.surveying || self.source != PositionSourceKind::Gpsd {
+            return;
+        }
+        let poll = tokio::process::Command::new("ubxtool").arg("-P").arg(UBLOX_VERSION).arg("-c").arg(NAV_SVIN_POLL).output();
+        let web_socket_monitor = self.web_socket_monitor.clone();
+        ctx.spawn(async move {
+            match poll.await {
+                Ok(output) => match SurveyStatus::from_ubxtool(&String::from_utf8_lossy(&output.stdout)) {
+                    Some(status) => web_socket_monitor.do_send(SurveyEvent { data: status }),
+                    None => log::warn!("No survey-in progress in the ubxtool NAV-SVIN poll output."),
+                },
+                Err(e) => log::error!("ubxtool failed to run: {}", e),
+            }
+        }.into_actor(self));
     }
 
-    fn run_ubx_commands(commands: Vec<(&str, &str)>) {
+    /// Run the ubxtool commands through gpsd, or on the receiver port when reading it directly. In that case the
+    /// serial source closes the port until the commands have run, so it doesn't read the acknowledgements.
+    fn run_ubx_commands(&self, commands: Vec<(&str, &str)>) -> std::io::Result<()> {
+        let _port = match self.source {
+            PositionSourceKind::Serial => Some(self.port_lock.take()),
+            PositionSourceKind::Gpsd => None,
+        };
         for (flag, configuration) in commands {
-            let output = Command::new("ubxtool").arg("-P").arg(UBLOX_VERSION).arg(flag).arg(configuration).output().expect("ubxtool failed  to run");
+            let mut command = Command::new("ubxtool");
+            if self.source == PositionSourceKind::Serial {
+                command.arg("-f").arg(&self.gps_usb_port).arg("-s").arg(GPS_BAUDRATE.to_string());
+            }
+            let output = command.arg("-P").arg(UBLOX_VERSION).arg(flag).arg(configuration).output().map_err(|e| {
+                log::error!("ubxtool failed to run: {}", e);
+                e
+            })?;
             let ubx_output = String::from_utf8_lossy(&output.stdout);
             if ubx_output.contains(UBX_ACK) {
                 log::info!("ubxtool successfully ran with {} {} flags.", flag, configuration);
@@ -262,74 +450,146 @@ impl GPSControl {
                 log::info!("ubxtool failed with {} {} flags.", flag, configuration);
             }
         }
+        Ok(())
     }
 }
 
 impl Actor for GPSControl {
     type Context = Context<Self>;
 
-    fn started(&mut self, _: &mut Self::Context) {
+    fn started(&mut self, ctx: &mut Self::Context) {
         let socket_addr = SocketAddr::new(self.ip_address, self.port);
 
         //Start the gpsd daemone if it's not already running.
-        if !port_scanner::scan_port_addr(&socket_addr) {
+        if self.source == PositionSourceKind::Gpsd && !port_scanner::scan_port_addr(&socket_addr) {
             log::info!("Starting GPSD Service.");
-            self.gpsd_command = Some(Command::new("gpsd")
-                                .arg(self.gps_usb_port.clone())
-                                .arg("-N")
-                                .spawn()
-                                .expect("The GPSD daemon failed to start."));
+            if let Err(e) = self.start_process(GPSD, "gpsd", vec![self.gps_usb_port.clone(), "-N".to_string()], RestartPolicy::Always) {
+                log::error!("{}", e);
+            }
             std::thread::sleep(std::time::Duration::from_secs(6));
-            let _output = Command::new ("gpsctl").arg("-s").arg(GPS_BAUDRATE).output().expect("Failed to execute gpsctl.");
-            log::info!("Baudrate set to {}.", GPS_BAUDRATE);
+            match Command::new ("gpsctl").arg("-s").arg(GPS_BAUDRATE.to_string()).output() {
+                Ok(_) => log::info!("Baudrate set to {}.", GPS_BAUDRATE),
+                Err(e) => log::error!("Failed to execute gpsctl: {}", e),
+            }
         }
 
-        self.set_rover_mode();
+        if let Err(e) = self.set_rover_mode() {
+            log::error!("Failed to set the GPS into rover mode: {}", e);
+        }
+        ctx.run_interval(SURVEY_POLL_INTERVAL, |act, ctx| act.poll_survey(ctx));
     }
 
     fn stopped(&mut self, _: &mut Self::Context) {
-        if let Some(mut cmd) = self.ntrip_command.take() {
-            cmd.kill().expect("str2str couldn't be killed!");
-        };
-        if let Some(mut cmd) = self.gpsd_command.take() {
-            cmd.kill().expect("gpsd couldn't be killed!");
-        };
-        if let Some(mut cmd) = self.rinex_collection_command.take() {
-            cmd.kill().expect("gpsrinex couldn't be killed!");
-        }
+        self.supervisor.do_send(StopAll);
     }
 }
 
 /// Implement handlers for various actor messages.
 impl Handler<GPSMode> for GPSControl {
-    type Result = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    type Result = ResponseActFuture<Self, Result<(), Box<dyn std::error::Error + Send + Sync>>>;
 
-    fn handle(&mut self, msg: GPSMode, ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: GPSMode, _ctx: &mut Context<Self>) -> Self::Result {
         log::info!("Handling set GPS mode in GPS control: {:?}", msg);
         match msg {
+            GPSMode::Stopped => (),
+            _ => self.stop_streams(),
+        };
+        let result = match msg {
             GPSMode::Base(username, password, server, mount_point, port,
-                          survey_dwell_time, survey_position_accuracy, 
-                          fixed_ecef_x, fixed_ecef_y, fixed_ecef_z, fixed_ecef_accuracy) => {
-                self.set_base_station_mode(&username, &password, &server, &mount_point, port,
-                                           survey_dwell_time, survey_position_accuracy, 
-                                           fixed_ecef_x, fixed_ecef_y, fixed_ecef_z, fixed_ecef_accuracy);
+                          survey_dwell_time, survey_position_accuracy, fixed_position) => {
+                self.set_rover_mode()
+                    .and_then(|_| self.set_base_station_mode(survey_dwell_time, survey_position_accuracy, fixed_position))
+                    .and_then(|_| self.start_ntrip_caster(&username, &password, &server, &mount_point, port))
                 //todo!( "Send set rover to LORA stack and other device." );
             },
             GPSMode::Standalone => {
-                self.set_rover_mode();
+                self.set_rover_mode()
                 //todo! ( "Send set base to LORA stack and other device." );
             },
             GPSMode::RAW(data_directory, filename, interval, number_of_collections) => { 
-                self.set_raw_mode(&data_directory, &filename, interval, number_of_collections);
+                self.set_raw_mode(&data_directory, &filename, interval, number_of_collections)
             }
             GPSMode::RtcmIn(username, password, server, mount_point, port) => {
-                self.set_rover_mode();
-                self.set_rtcm_input_mode(&username, &password, &server, &mount_point, port);
+                self.set_rover_mode().and_then(|_| self.set_rtcm_input_mode(&username, &password, &server, &mount_point, port))
             },
             GPSMode::Stopped => {
-                ctx.stop();
+                //Wait for the children to exit before replying, so the caller knows they have stopped.
+                let stop_all = self.supervisor.send(StopAll);
+                return Box::pin(async move { stop_all.await }.into_actor(self).map(|result, _act, ctx| {
+                    ctx.stop();
+                    result.map_err(|e| e.into())
+                }));
             }
+        };
+        Box::pin(fut::ready(result.map_err(|e| e.into())))
+    }
+}
+impl Handler<GetControlStatus> for GPSControl {
+    type Result = MessageResult<GetControlStatus>;
+
+    fn handle(&mut self, _msg: GetControlStatus, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(ControlStatus {
+            receiver_device: self.gps_usb_port.clone(),
+            receiver_present: std::path::Path::new(&self.gps_usb_port).exists(),
+        })
+    }
+}
+
+impl Handler<SettingsChanged> for GPSControl {
+    type Result = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    fn handle(&mut self, msg: SettingsChanged, _ctx: &mut Context<Self>) -> Self::Result {
+        log::info!("Applying changed settings in GPS control.");
+        let (old_fixed_position, fixed_position) = (msg.old.fixed_position(), msg.new.fixed_position());
+        match (msg.old, msg.new) {
+            (Modes::RTKRover{username: old_username, password: old_password, server: old_server, mount_point: old_mount_point, port: old_port},
+             Modes::RTKRover{username, password, server, mount_point, port}) => {
+                if (old_username, old_password, old_server, old_mount_point, old_port) != (username.clone(), password.clone(), server.clone(), mount_point.clone(), port) {
+                    self.set_rtcm_input_mode(&username, &password, &server, &mount_point, port)?;
+                }
+            },
+            (Modes::RTKBase{username: old_username, password: old_password, server: old_server, mount_point: old_mount_point, port: old_port,
+                            survey_dwell_time: old_survey_dwell_time, survey_position_accuracy: old_survey_position_accuracy, ..},
+             Modes::RTKBase{username, password, server, mount_point, port, survey_dwell_time, survey_position_accuracy, ..}) => {
+                if (old_survey_dwell_time, old_survey_position_accuracy, old_fixed_position) != (survey_dwell_time, survey_position_accuracy, fixed_position) {
+                    log::info!("Base station position settings changed, re-sending the time mode.");
+                    self.set_base_time_mode(survey_dwell_time, survey_position_accuracy, fixed_position)?;
+                }
+                if (old_username, old_password, old_server, old_mount_point, old_port) != (username.clone(), password.clone(), server.clone(), mount_point.clone(), port) {
+                    log::info!("NTRIP settings changed, restarting the ntrip caster.");
+                    self.start_ntrip_caster(&username, &password, &server, &mount_point, port)?;
+                }
+            },
+            (Modes::PPPMode{..}, Modes::PPPMode{data_directory, filename, interval, number_of_collections}) => {
+                log::info!("Raw collection settings changed, restarting the RINEX collection.");
+                self.start_rinex_collection(&data_directory, &filename, interval, number_of_collections)?;
+            },
+            (Modes::Standalone, Modes::Standalone) => (),
+            _ => return Err("The changed settings are for a different mode than the active one.".into()),
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_fixed_position_items () {
+        let ecef = FixedPosition::Ecef(Ecef { x: 918_502.1235, y: -4_346_271.3312, z: 4_561_131.06 }, 0.0145);
+        assert_eq! (fixed_position_items(&ecef), vec!["CFG-TMODE-POS_TYPE,0",
+                                                      "CFG-TMODE-ECEF_X,91850212", "CFG-TMODE-ECEF_X_HP,35",
+                                                      "CFG-TMODE-ECEF_Y,-434627133", "CFG-TMODE-ECEF_Y_HP,-12",
+                                                      "CFG-TMODE-ECEF_Z,456113106", "CFG-TMODE-ECEF_Z_HP,0",
+                                                      "CFG-TMODE-FIXED_POS_ACC,145"]);
+
+        let llh = FixedPosition::Llh(Llh { latitude: 45.123_456_789, longitude: -75.987_654_321, height: 100.1234 }, 0.02);
+        assert_eq! (fixed_position_items(&llh), vec!["CFG-TMODE-POS_TYPE,1",
+                                                     "CFG-TMODE-LAT,451234567", "CFG-TMODE-LAT_HP,89",
+                                                     "CFG-TMODE-LON,-759876543", "CFG-TMODE-LON_HP,-21",
+                                                     "CFG-TMODE-HEIGHT,10012", "CFG-TMODE-HEIGHT_HP,34",
+                                                     "CFG-TMODE-FIXED_POS_ACC,200"]);
+    }
+}
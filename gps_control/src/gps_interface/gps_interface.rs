@@ -1,162 +1,530 @@
 
 use futures::prelude::*;
-use gpsd_proto::UnifiedResponse;
-use tokio::net::TcpStream;
-use tokio_util::codec::Framed;
-use tokio_util::codec::LinesCodec;
+use gpsd_proto::{Mode, Satellite, Sky};
 use serde::Serialize;
 use actix::prelude::*;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant, SystemTime};
 
+use crate::geodesy::coordinates::{Ecef, Llh};
 use crate::web_socket;
+use super::chrony::{ChronySample, ChronySocket, LeapIndicator};
+use super::pps::{PpsMonitor, PpsReport};
+use super::position_source::PositionSource;
+use super::survey::SurveyStatus;
 
-#[derive(Serialize, Clone, Debug)]
-pub struct GPSData {
-    device_path: String,
-    driver: String,
-    activated: String,
-
-    mode: String,
-    lat: f64,
-    lon: f64,
-    alt: f32,
-    track: f32,
-    speed: f32,
-    time: String,
-    rms: f32,
-    orient: f32,
-    major: f32,
-    minor: f32,
-}
-
-///The GPSD interface. Runs asynchronously from the GPSD server.
-pub struct GPSInterface {
-    ip_address: IpAddr,
-    port: u16,
-    web_socket_monitor: Addr<web_socket::GPSWebSocketMonitor>,
+/// Delay before the first reconnection attempt, doubled on every failed attempt up to MAX_RECONNECT_DELAY.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// How often the data is checked for going stale.
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
-    gps: GPSData,
+/// Quality of the position fix, combining the TPV mode and status.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum FixQuality {
+    #[serde(rename = "none")]
+    NoFix,
+    #[serde(rename = "2d")]
+    Fix2D,
+    #[serde(rename = "3d")]
+    Fix3D,
+    #[serde(rename = "dgps")]
+    DGPS,
+    #[serde(rename = "rtk_float")]
+    RTKFloat,
+    #[serde(rename = "rtk_fixed")]
+    RTKFixed,
 }
 
-impl GPSInterface {
+impl FixQuality {
+    /// Work out the fix quality from the TPV mode and status (2 DGPS, 3 RTK fixed, 4 RTK float).
+    pub(super) fn from_tpv(mode: &Mode, status: Option<i32>) -> Self {
+        match (mode, status) {
+            (Mode::NoFix, _) => FixQuality::NoFix,
+            (_, Some(2)) => FixQuality::DGPS,
+            (_, Some(3)) => FixQuality::RTKFixed,
+            (_, Some(4)) => FixQuality::RTKFloat,
+            (Mode::Fix2d, _) => FixQuality::Fix2D,
+            (Mode::Fix3d, _) => FixQuality::Fix3D,
+        }
+    }
+}
 
-    /// Generate an empty GPSD interface.
-    pub fn new (ip_address: Option<&str>, 
-                port: Option<u16>, 
-                web_socket_monitor: Addr<web_socket::GPSWebSocketMonitor>) -> Self {
-        let ip_address = match ip_address {
-            Some(ip) => ip,
-            None => "127.0.0.1"
-        };
-        let port = match port {
-            Some(p) => p,
-            None => 2947
-        };
-
-        let ip_address = match ip_address.parse::<IpAddr>() {
-            Ok(val) => val,
-            Err(e) => {
-                log::error!("Failed to parse input ip address: {:?}", e);
-                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))
-            }
-        };
+#[derive(Serialize, Clone, Debug)]
+pub struct GPSData {
+    pub(super) device_path: String,
+    pub(super) driver: String,
+    pub(super) activated: String,
 
-        let gps = GPSData {
-            device_path: "".to_string(),
+    pub(super) fix: FixQuality,
+    /// Raw TPV status, 0 when unknown.
+    pub(super) status: i32,
+    pub(super) lat: f64,
+    pub(super) lon: f64,
+    pub(super) alt: f64,
+    pub(super) track: f32,
+    pub(super) speed: f32,
+    pub(super) climb: Option<f32>,
+    pub(super) time: String,
+    /// Estimated errors in m (m/s for eps), at 95% confidence.
+    pub(super) epx: Option<f64>,
+    pub(super) epy: Option<f64>,
+    pub(super) epv: Option<f64>,
+    pub(super) eph: Option<f64>,
+    pub(super) eps: Option<f64>,
+    /// Horizontal and vertical accuracy estimates in m, as reported by a u-blox receiver read directly.
+    pub(super) h_acc: Option<f64>,
+    pub(super) v_acc: Option<f64>,
+    /// Velocity north, east and down in m/s.
+    pub(super) vel_n: Option<f64>,
+    pub(super) vel_e: Option<f64>,
+    pub(super) vel_d: Option<f64>,
+    pub(super) satellites_used: Option<u8>,
+    /// Seconds from the receiver starting to its first fix.
+    pub(super) time_to_first_fix: Option<f64>,
+    pub(super) ecef_x: Option<f64>,
+    pub(super) ecef_y: Option<f64>,
+    pub(super) ecef_z: Option<f64>,
+    pub(super) geoid_sep: Option<f32>,
+    /// Age of the differential corrections in s, and the id of the station sending them.
+    pub(super) dgps_age: Option<f32>,
+    pub(super) dgps_station: Option<i32>,
+    pub(super) rms: f64,
+    pub(super) orient: f64,
+    pub(super) major: f64,
+    pub(super) minor: f64,
+    /// Standard deviations of the latitude, longitude and altitude errors in m, from GST.
+    pub(super) lat_err: Option<f64>,
+    pub(super) lon_err: Option<f64>,
+    pub(super) alt_err: Option<f64>,
+    /// No TPV has been received within the stale timeout, so the position is out of date.
+    pub(super) stale: bool,
+}
+
+impl GPSData {
+    pub(super) fn new(device_path: &str) -> Self {
+        GPSData {
+            device_path: device_path.to_string(),
             driver: "".to_string(),
             activated: "".to_string(),
 
-            mode: "".to_string(),
+            fix: FixQuality::NoFix,
+            status: 0,
             lat: 0.,
             lon: 0.,
             alt: 0.,
             track: 0.,
             speed: 0.,
+            climb: None,
             time: "".to_string(),
+            epx: None,
+            epy: None,
+            epv: None,
+            eph: None,
+            eps: None,
+            h_acc: None,
+            v_acc: None,
+            vel_n: None,
+            vel_e: None,
+            vel_d: None,
+            satellites_used: None,
+            time_to_first_fix: None,
+            ecef_x: None,
+            ecef_y: None,
+            ecef_z: None,
+            geoid_sep: None,
+            dgps_age: None,
+            dgps_station: None,
             rms: 0.,
             orient: 0.,
             major: 0.,
             minor: 0.,
-        };
+            lat_err: None,
+            lon_err: None,
+            alt_err: None,
+            stale: true,
+        }
+    }
 
-        GPSInterface{
-            ip_address: ip_address,
-            port: port,
+    /// Path of the receiver the data is from.
+    pub fn device(&self) -> &str {
+        &self.device_path
+    }
+
+    pub fn fix(&self) -> FixQuality {
+        self.fix
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Position on the ellipsoid, None without a fix. The altitude is above mean sea level, so the height
+    /// is only ellipsoidal when the geoid separation is known.
+    pub fn llh(&self) -> Option<Llh> {
+        if self.fix == FixQuality::NoFix {
+            return None;
+        }
+        Some(Llh { latitude: self.lat, longitude: self.lon, height: self.alt + self.geoid_sep.unwrap_or(0.) as f64 })
+    }
+
+    /// ECEF position, as reported by the receiver or else worked out from the latitude, longitude and height.
+    pub fn ecef(&self) -> Option<Ecef> {
+        match (self.ecef_x, self.ecef_y, self.ecef_z) {
+            (Some(x), Some(y), Some(z)) if self.fix != FixQuality::NoFix => Some(Ecef { x: x, y: y, z: z }),
+            _ => self.llh().map(|llh| llh.to_ecef()),
+        }
+    }
+}
+
+/// Satellite system, from the gnssid reported by gpsd.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Constellation {
+    GPS,
+    SBAS,
+    Galileo,
+    BeiDou,
+    IMES,
+    QZSS,
+    GLONASS,
+    NavIC,
+    Unknown,
+}
+
+impl Constellation {
+    /// Use the gnssid when gpsd reports it, otherwise fall back on the NMEA PRN ranges.
+    fn from_satellite(gnssid: Option<u8>, prn: i16) -> Self {
+        match (gnssid, prn) {
+            (Some(0), _) => Constellation::GPS,
+            (Some(1), _) => Constellation::SBAS,
+            (Some(2), _) => Constellation::Galileo,
+            (Some(3), _) => Constellation::BeiDou,
+            (Some(4), _) => Constellation::IMES,
+            (Some(5), _) => Constellation::QZSS,
+            (Some(6), _) => Constellation::GLONASS,
+            (Some(7), _) => Constellation::NavIC,
+            (Some(_), _) => Constellation::Unknown,
+            (None, 1..=63) => Constellation::GPS,
+            (None, 64..=96) => Constellation::GLONASS,
+            (None, 120..=158) => Constellation::SBAS,
+            (None, 193..=197) => Constellation::QZSS,
+            (None, 201..=235) | (None, 401..=437) => Constellation::BeiDou,
+            (None, 301..=336) => Constellation::Galileo,
+            (None, _) => Constellation::Unknown,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SatelliteData {
+    pub prn: i16,
+    pub constellation: Constellation,
+    /// Satellite id within its constellation.
+    pub svid: Option<u16>,
+    /// Elevation and azimuth in degrees.
+    pub elevation: Option<f32>,
+    pub azimuth: Option<f32>,
+    /// Signal to noise ratio in dB-Hz.
+    pub snr: Option<f32>,
+    pub used: bool,
+}
+
+impl From<&Satellite> for SatelliteData {
+    fn from(satellite: &Satellite) -> Self {
+        SatelliteData {
+            prn: satellite.prn,
+            constellation: Constellation::from_satellite(satellite.gnssid, satellite.prn),
+            svid: satellite.svid,
+            elevation: satellite.el,
+            azimuth: satellite.az,
+            snr: satellite.ss,
+            used: satellite.used,
+        }
+    }
+}
+
+/// Satellites in view and dilution of precision values from the gpsd SKY report.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct SkyData {
+    /// Path of the receiver the satellites are seen by.
+    pub device: String,
+    pub satellites: Vec<SatelliteData>,
+    pub satellites_used: usize,
+    pub xdop: Option<f32>,
+    pub ydop: Option<f32>,
+    pub vdop: Option<f32>,
+    pub tdop: Option<f32>,
+    pub hdop: Option<f32>,
+    pub gdop: Option<f32>,
+    pub pdop: Option<f32>,
+}
+
+impl From<Sky> for SkyData {
+    fn from(sky: Sky) -> Self {
+        let satellites: Vec<SatelliteData> = sky.satellites.iter().map(SatelliteData::from).collect();
+        SkyData {
+            device: sky.device.unwrap_or_default(),
+            satellites_used: satellites.iter().filter(|satellite| satellite.used).count(),
+            satellites: satellites,
+            xdop: sky.xdop,
+            ydop: sky.ydop,
+            vdop: sky.vdop,
+            tdop: sky.tdop,
+            hdop: sky.hdop,
+            gdop: sky.gdop,
+            pdop: sky.pdop,
+        }
+    }
+}
+
+/// A receiver reported by gpsd, or read directly.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DeviceState {
+    pub path: String,
+    pub driver: String,
+    /// gpsd (or gps_control) has the receiver device open.
+    pub receiver_active: bool,
+}
+
+/// State of the connection to the position source, and of the receivers as reported by it.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct GPSConnectionState {
+    /// Connected to gpsd, or to the receiver when it's read directly.
+    pub gpsd_connected: bool,
+    pub devices: Vec<DeviceState>,
+}
+
+/// State of the receivers, updated by the position source and passed on to the web sockets.
+pub struct ReceiverState {
+    web_socket_monitor: Addr<web_socket::GPSWebSocketMonitor>,
+
+    /// Data of each receiver, by device path.
+    gps: BTreeMap<String, GPSData>,
+    connection: GPSConnectionState,
+    stale_timeout: Duration,
+    last_position: HashMap<String, Instant>,
+    last_check: Instant,
+    pps: PpsMonitor,
+    chrony: Option<ChronySocket>,
+}
+
+impl ReceiverState {
+    fn new(web_socket_monitor: Addr<web_socket::GPSWebSocketMonitor>, stale_timeout: Duration, chrony: Option<ChronySocket>) -> Self {
+        ReceiverState {
             web_socket_monitor: web_socket_monitor,
+            gps: BTreeMap::new(),
+            connection: GPSConnectionState::default(),
+            stale_timeout: stale_timeout,
+            last_position: HashMap::new(),
+            last_check: Instant::now(),
+            pps: PpsMonitor::default(),
+            chrony: chrony,
+        }
+    }
 
-            gps: gps,
+    /// Update the connection state, passing it on to the web socket monitor when it changes.
+    fn set_connection_state(&mut self, connection: GPSConnectionState) {
+        if connection != self.connection {
+            self.connection = connection;
+            self.web_socket_monitor.do_send(web_socket::GPSConnectionEvent { state: self.connection.clone() });
         }
     }
 
-    /// Start the handler for reading in GPS data. 
-    /// This process will start the docker instance of the gpsd daemon if it isn't already running.
-    pub async fn run_handler(self: &mut Self) {
+    /// The source has connected to gpsd or the receiver.
+    pub(super) fn connected(&mut self) {
+        self.set_connection_state(GPSConnectionState { gpsd_connected: true, ..self.connection.clone() });
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connection.gpsd_connected
+    }
+
+    /// The connection to the source is lost, so all the data goes stale.
+    fn disconnected(&mut self) {
+        self.set_connection_state(GPSConnectionState::default());
+        self.last_position.clear();
+        self.check_stale();
+    }
+
+    /// Record a receiver reported by the source, with gpsd's activation time if any.
+    pub(super) fn update_device(&mut self, device: DeviceState, activated: String) {
+        let path = device.path.clone();
+        let gps = self.gps.entry(path.clone()).or_insert_with(|| GPSData::new(&path));
+        gps.driver = device.driver.clone();
+        gps.activated = activated;
+
+        let mut connection = self.connection.clone();
+        connection.gpsd_connected = true;
+        match connection.devices.iter_mut().find(|known| known.path == path) {
+            Some(known) => *known = device,
+            None => connection.devices.push(device),
+        }
+        self.set_connection_state(connection);
+    }
+
+    /// Device the report is for. gpsd leaves the device out when there's only one.
+    pub(super) fn device_key(&self, device: Option<String>) -> String {
+        match device {
+            Some(device) => device,
+            None if self.connection.devices.len() == 1 => self.connection.devices[0].path.clone(),
+            None => "".to_string(),
+        }
+    }
+
+    pub(super) fn device_data(&mut self, device: Option<String>) -> &mut GPSData {
+        let key = self.device_key(device);
+        self.gps.entry(key.clone()).or_insert_with(|| GPSData::new(&key))
+    }
+
+    /// A new position has been filled in for the device, so its data is no longer stale.
+    pub(super) fn position_received(&mut self, device: &str) {
+        self.last_position.insert(device.to_string(), Instant::now());
+        self.device_data(Some(device.to_string())).stale = false;
+        self.send_data(device);
+    }
+
+    /// Send the device's data to the web sockets.
+    pub(super) fn send_data(&mut self, device: &str) {
+        let data = self.device_data(Some(device.to_string())).clone();
+        self.web_socket_monitor.do_send(web_socket::GPSEvent {data: data});
+    }
+
+    pub(super) fn sky_received(&mut self, sky: SkyData) {
+        self.web_socket_monitor.do_send(web_socket::SkyEvent {data: sky});
+    }
+
+    pub(super) fn survey_received(&mut self, survey: SurveyStatus) {
+        self.web_socket_monitor.do_send(web_socket::SurveyEvent {data: survey});
+    }
+
+    /// Record a PPS pulse and pass it on to chrony.
+    pub(super) fn pps_received(&mut self, report: &PpsReport, leap: LeapIndicator) {
+        let now = Instant::now();
+        self.pps.add_pulse(report, now);
+        if let Some(chrony) = &mut self.chrony {
+            chrony.send(&ChronySample::new(report.clock_sec, report.clock_nsec, report.real_sec, report.real_nsec, true, leap));
+        }
+        self.web_socket_monitor.do_send(web_socket::TimeEvent {data: self.pps.data(now)});
+    }
+
+    /// Pass the time of a navigation solution to chrony, with the system time the message was received at.
+    /// The message arrives a while after the time it is for, which the offset of chrony's refclock corrects.
+    pub(super) fn time_received(&mut self, received: SystemTime, real_sec: i64, real_nsec: i64, leap: LeapIndicator) {
+        if let Some(chrony) = &mut self.chrony {
+            let clock = received.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+            chrony.send(&ChronySample::new(clock.as_secs() as i64, clock.subsec_nanos() as i64, real_sec, real_nsec, false, leap));
+        }
+    }
+
+    /// Mark the data of each receiver as stale if no position has arrived within the stale timeout, letting the web sockets know.
+    fn check_stale(&mut self) {
+        for (device, gps) in self.gps.iter_mut() {
+            let stale = self.last_position.get(device).is_none_or(|time| time.elapsed() > self.stale_timeout);
+            if stale && !gps.stale {
+                log::warn!("No position from {} for {} s, marking the data as stale.", device, self.stale_timeout.as_secs());
+                gps.stale = true;
+                self.web_socket_monitor.do_send(web_socket::GPSEvent {data: gps.clone()});
+            }
+        }
+    }
 
-        let socket_addr = SocketAddr::new(self.ip_address, self.port);
-
-        let socket = TcpStream::connect(&socket_addr).await.expect( &format!("Failed to connect to the GPSD daemon at ip {}:{}", self.ip_address, self.port) );
-
-        let mut framed = Framed::new(socket, LinesCodec::new());
-
-        framed.send(gpsd_proto::ENABLE_WATCH_CMD.to_string()).await.expect("Failed to get expected response from GPSD.");
-
-        while let Some(Ok(line)) = framed.next().await {
-
-            match serde_json::from_str(&line) {
-                Ok(rd) => match rd {
-                    UnifiedResponse::Version(v) => {
-                        if v.proto_major < gpsd_proto::PROTO_MAJOR_MIN {
-                            panic!("Gpsd major version mismatch");
-                        }
-                        log::info!("Gpsd version {} connected", v.rev);
-                    }
-                    UnifiedResponse::Devices(_) => {}
-                    UnifiedResponse::Watch(_) => {}
-                    UnifiedResponse::Device(d) => {
-                        //log::debug!("Device {:?}", d);
-                        self.gps.device_path = d.path.unwrap_or("".to_string());
-                        self.gps.driver = d.driver.unwrap_or("".to_string());
-                        self.gps.activated = d.activated.unwrap_or("".to_string());
-                    },
-                    UnifiedResponse::Tpv(t) => {
-                        //log::debug!("Tpv {:?}", t);
-                        self.gps.mode = t.mode.to_string();
-                        self.gps.lat = t.lat.unwrap_or(0.0);
-                        self.gps.lon = t.lon.unwrap_or(0.0);
-                        self.gps.alt = t.alt.unwrap_or(0.0);
-                        self.gps.track = t.track.unwrap_or(0.0);
-                        self.gps.speed = t.speed.unwrap_or(0.0);
-                        self.gps.time = t.time.unwrap_or("".to_string());
-                    },
-                    UnifiedResponse::Sky(_s) => {}//log::debug!("Sky {:?}", s),
-                    UnifiedResponse::Pps(p) => log::debug!("PPS {:?}", p),
-                    UnifiedResponse::Gst(g) => {
-                        //log::debug!("GST {:?}", g);
-                        //g.device.unwrap_or("".to_string());
-                        self.gps.time = g.time.unwrap_or("".to_string());
-                        self.gps.rms = g.rms.unwrap_or(0.);
-                        self.gps.major = g.major.unwrap_or(0.);
-                        self.gps.minor = g.minor.unwrap_or(0.); 
-                        self.gps.orient = g.orient.unwrap_or(0.);
-                        self.gps.lat = g.lat.unwrap_or(0.).into(); 
-                        self.gps.lon = g.lon.unwrap_or(0.).into();
-                        self.gps.alt = g.alt.unwrap_or(0.);
-                    },
-                    //need to add RAW support to gpsd_proto
-                },
-                Err(_e) => {
-                    //log::error!("Error decoding: {}", e);
-                }
-            };
-
-            let gps_event = web_socket::GPSEvent {data: self.gps.clone()};
-            self.web_socket_monitor.do_send(gps_event);
+    fn check_timeouts(&mut self) {
+        self.check_stale();
+        if self.pps.check_lost(Instant::now()) {
+            self.web_socket_monitor.do_send(web_socket::TimeEvent {data: self.pps.data(Instant::now())});
         }
+        self.last_check = Instant::now();
+    }
 
+    /// Wait for the next message from the source, checking for stale data and a lost PPS while waiting.
+    pub(super) async fn next<S: Stream + Unpin>(&mut self, stream: &mut S) -> Option<S::Item> {
+        loop {
+            let wait = STALE_CHECK_INTERVAL.saturating_sub(self.last_check.elapsed());
+            match tokio::time::timeout(wait, stream.next()).await {
+                Ok(item) => return item,
+                Err(_) => self.check_timeouts(),
+            }
+        }
     }
+}
 
+///The GPS interface. Reads the receiver data from the position source (gpsd or the receiver itself), asynchronously.
+pub struct GPSInterface {
+    source: Box<dyn PositionSource>,
+    state: ReceiverState,
 }
 
+impl GPSInterface {
 
+    /// Generate an empty GPS interface.
+    ///  - source: where the receiver data is read from.
+    ///  - stale_timeout: time without a position after which the data is marked as stale.
+    ///  - chrony: chrony SOCK refclock socket the PPS pulses are passed on to, if any.
+    pub fn new (source: Box<dyn PositionSource>,
+                web_socket_monitor: Addr<web_socket::GPSWebSocketMonitor>,
+                stale_timeout: Duration,
+                chrony: Option<ChronySocket>) -> Self {
+        GPSInterface{
+            source: source,
+            state: ReceiverState::new(web_socket_monitor, stale_timeout, chrony),
+        }
+    }
 
+    /// Start the handler for reading in GPS data. The connection to the source is retried with an increasing delay
+    /// whenever it can't be made or is lost.
+    pub async fn run_handler(self: &mut Self) {
+        let mut delay = INITIAL_RECONNECT_DELAY;
+        loop {
+            let result = self.source.run(&mut self.state).await;
+            let connected = self.state.is_connected();
+            match result {
+                Ok(()) => log::error!("Lost the connection to {}.", self.source.describe()),
+                Err(e) if connected => log::error!("Error reading from {}: {}", self.source.describe(), e),
+                Err(e) => log::error!("Failed to connect to {}: {}", self.source.describe(), e),
+            }
+            if connected {
+                delay = INITIAL_RECONNECT_DELAY;
+            }
+            self.state.disconnected();
 
+            log::info!("Reconnecting to {} in {} s.", self.source.describe(), delay.as_secs());
+            tokio::time::sleep(delay).await;
+            delay = next_reconnect_delay(delay);
+        }
+    }
+}
+
+fn next_reconnect_delay(delay: Duration) -> Duration {
+    std::cmp::min(delay * 2, MAX_RECONNECT_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_reconnect_delay () {
+        assert_eq! (next_reconnect_delay(INITIAL_RECONNECT_DELAY), Duration::from_secs(2));
+        assert_eq! (next_reconnect_delay(Duration::from_secs(20)), MAX_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn test_fix_quality () {
+        assert_eq! (FixQuality::from_tpv(&Mode::NoFix, Some(3)), FixQuality::NoFix);
+        assert_eq! (FixQuality::from_tpv(&Mode::Fix3d, Some(3)), FixQuality::RTKFixed);
+        assert_eq! (FixQuality::from_tpv(&Mode::Fix3d, Some(4)), FixQuality::RTKFloat);
+        assert_eq! (FixQuality::from_tpv(&Mode::Fix3d, Some(2)), FixQuality::DGPS);
+        assert_eq! (FixQuality::from_tpv(&Mode::Fix2d, Some(1)), FixQuality::Fix2D);
+        assert_eq! (FixQuality::from_tpv(&Mode::Fix3d, None), FixQuality::Fix3D);
+    }
+
+    #[test]
+    fn test_constellation () {
+        assert_eq! (Constellation::from_satellite(Some(2), 11), Constellation::Galileo);
+        assert_eq! (Constellation::from_satellite(None, 12), Constellation::GPS);
+        assert_eq! (Constellation::from_satellite(None, 70), Constellation::GLONASS);
+        assert_eq! (Constellation::from_satellite(None, 131), Constellation::SBAS);
+    }
+}
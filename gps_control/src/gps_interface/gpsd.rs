@@ -0,0 +1,210 @@
+use futures::prelude::*;
+use futures::future::BoxFuture;
+use gpsd_proto::UnifiedResponse;
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+use tokio_util::codec::LinesCodec;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use super::chrony::LeapIndicator;
+use super::gps_interface::{DeviceState, FixQuality, ReceiverState, SkyData};
+use super::pps::PpsReport;
+use super::position_source::{PositionSource, SourceResult};
+
+type GPSDStream = Framed<TcpStream, LinesCodec>;
+
+/// TPV fields that gpsd_proto doesn't decode.
+#[derive(Deserialize, Default)]
+struct TpvExtras {
+    ecefx: Option<f64>,
+    ecefy: Option<f64>,
+    ecefz: Option<f64>,
+    #[serde(rename = "dgpsAge")]
+    dgps_age: Option<f32>,
+    #[serde(rename = "dgpsSta")]
+    dgps_station: Option<i32>,
+}
+
+/// DEVICE or entry of the DEVICES report. gpsd_proto keeps the DEVICES list private, so both are decoded here.
+#[derive(Deserialize)]
+struct DeviceReport {
+    path: Option<String>,
+    driver: Option<String>,
+    activated: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DevicesReport {
+    devices: Vec<DeviceReport>,
+}
+
+/// Record a receiver from a DEVICE or DEVICES report.
+fn update_device(state: &mut ReceiverState, report: DeviceReport) {
+    let activated = report.activated.unwrap_or_default();
+    state.update_device(DeviceState {
+        path: report.path.unwrap_or_default(),
+        driver: report.driver.unwrap_or_default(),
+        receiver_active: !activated.is_empty() && activated != "0",
+    }, activated);
+}
+
+///The GPSD client, reading the JSON reports of the receivers gpsd is watching.
+pub struct GpsdSource {
+    ip_address: IpAddr,
+    port: u16,
+}
+
+impl GpsdSource {
+    /// Set up the client of the GPSD daemon at the given address, 127.0.0.1:2947 by default.
+    pub fn new(ip_address: Option<&str>, port: Option<u16>) -> Self {
+        let ip_address = match ip_address {
+            Some(ip) => ip,
+            None => "127.0.0.1"
+        };
+        let port = match port {
+            Some(p) => p,
+            None => 2947
+        };
+
+        let ip_address = match ip_address.parse::<IpAddr>() {
+            Ok(val) => val,
+            Err(e) => {
+                log::error!("Failed to parse input ip address: {:?}", e);
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))
+            }
+        };
+
+        GpsdSource {
+            ip_address: ip_address,
+            port: port,
+        }
+    }
+
+    /// Connect to gpsd and start watching the GPS reports.
+    async fn connect(&mut self) -> Result<GPSDStream, Box<dyn std::error::Error + Send + Sync>> {
+        let socket_addr = SocketAddr::new(self.ip_address, self.port);
+        let socket = TcpStream::connect(&socket_addr).await?;
+        let mut framed = Framed::new(socket, LinesCodec::new());
+        framed.send(gpsd_proto::ENABLE_WATCH_CMD.to_string()).await?;
+
+        log::info!("Connected to the GPSD daemon at ip {}:{}.", self.ip_address, self.port);
+        Ok(framed)
+    }
+
+    /// Update the GPS data from a gpsd report and send it to the web sockets.
+    fn handle_line(&mut self, line: &str, state: &mut ReceiverState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match serde_json::from_str(line) {
+            Ok(rd) => match rd {
+                UnifiedResponse::Version(v) => {
+                    if v.proto_major < gpsd_proto::PROTO_MAJOR_MIN {
+                        return Err(format!("Gpsd major version mismatch, {} is too old", v.rev).into());
+                    }
+                    log::info!("Gpsd version {} connected", v.rev);
+                }
+                UnifiedResponse::Devices(_) => {
+                    match serde_json::from_str::<DevicesReport>(line) {
+                        Ok(report) => report.devices.into_iter().for_each(|device| update_device(state, device)),
+                        Err(e) => log::error!("Failed to decode the GPSD device list: {}", e),
+                    }
+                }
+                UnifiedResponse::Watch(_) => {}
+                UnifiedResponse::Device(_) => {
+                    match serde_json::from_str::<DeviceReport>(line) {
+                        Ok(report) => update_device(state, report),
+                        Err(e) => log::error!("Failed to decode the GPSD device: {}", e),
+                    }
+                },
+                UnifiedResponse::Tpv(t) => {
+                    //log::debug!("Tpv {:?}", t);
+                    let extras: TpvExtras = serde_json::from_str(line).unwrap_or_default();
+                    let device = state.device_key(t.device.clone());
+                    let gps = state.device_data(Some(device.clone()));
+                    gps.fix = FixQuality::from_tpv(&t.mode, t.status);
+                    gps.status = t.status.unwrap_or(0);
+                    gps.lat = t.lat.unwrap_or(0.0);
+                    gps.lon = t.lon.unwrap_or(0.0);
+                    gps.alt = t.alt.unwrap_or(0.0).into();
+                    gps.track = t.track.unwrap_or(0.0);
+                    gps.speed = t.speed.unwrap_or(0.0);
+                    gps.climb = t.climb;
+                    gps.time = t.time.unwrap_or("".to_string());
+                    gps.epx = t.epx.map(f64::from);
+                    gps.epy = t.epy.map(f64::from);
+                    gps.epv = t.epv.map(f64::from);
+                    gps.eph = t.eph.map(f64::from);
+                    gps.eps = t.eps.map(f64::from);
+                    gps.ecef_x = extras.ecefx;
+                    gps.ecef_y = extras.ecefy;
+                    gps.ecef_z = extras.ecefz;
+                    gps.geoid_sep = t.geoid_sep;
+                    gps.dgps_age = extras.dgps_age;
+                    gps.dgps_station = extras.dgps_station;
+                    state.position_received(&device);
+                },
+                UnifiedResponse::Sky(s) => {
+                    state.sky_received(SkyData::from(s));
+                },
+                UnifiedResponse::Pps(p) => {
+                    log::debug!("PPS {:?}", p);
+                    match serde_json::from_str::<PpsReport>(line) {
+                        //gpsd doesn't pass on the receiver's leap second warning.
+                        Ok(report) => state.pps_received(&report, LeapIndicator::None),
+                        Err(e) => log::error!("Failed to decode the PPS report: {}", e),
+                    }
+                },
+                UnifiedResponse::Gst(g) => {
+                    //log::debug!("GST {:?}", g);
+                    let device = state.device_key(g.device.clone());
+                    let gps = state.device_data(Some(device.clone()));
+                    gps.time = g.time.unwrap_or("".to_string());
+                    gps.rms = g.rms.unwrap_or(0.).into();
+                    gps.major = g.major.unwrap_or(0.).into();
+                    gps.minor = g.minor.unwrap_or(0.).into();
+                    gps.orient = g.orient.unwrap_or(0.).into();
+                    //The GST latitude, longitude and altitude are error sigmas, not a position.
+                    gps.lat_err = g.lat.map(f64::from);
+                    gps.lon_err = g.lon.map(f64::from);
+                    gps.alt_err = g.alt.map(f64::from);
+                    state.send_data(&device);
+                },
+                //need to add RAW support to gpsd_proto
+            },
+            Err(_e) => {
+                //log::error!("Error decoding: {}", e);
+            }
+        };
+        Ok(())
+    }
+}
+
+impl PositionSource for GpsdSource {
+    fn describe(&self) -> String {
+        format!("the GPSD daemon at {}:{}", self.ip_address, self.port)
+    }
+
+    fn run<'a>(&'a mut self, state: &'a mut ReceiverState) -> BoxFuture<'a, SourceResult> {
+        Box::pin(async move {
+            let mut framed = self.connect().await?;
+            state.connected();
+            while let Some(line) = state.next(&mut framed).await {
+                self.handle_line(&line?, state)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_tpv_extras () {
+        let line = r#"{"class":"TPV","mode":3,"status":3,"ecefx":918502.12,"ecefy":-4346271.33,"ecefz":4561131.06,"dgpsAge":1.0,"dgpsSta":2000}"#;
+        let extras: TpvExtras = serde_json::from_str(line).unwrap();
+        assert_eq! (extras.ecefx, Some(918502.12));
+        assert_eq! (extras.dgps_station, Some(2000));
+    }
+}
@@ -1,2 +1,9 @@
+pub mod chrony;
 pub mod gps_control;
-pub mod gps_interface;
\ No newline at end of file
+pub mod gps_interface;
+pub mod gpsd;
+pub mod position_source;
+pub mod pps;
+pub mod serial;
+pub mod survey;
+pub mod ubx;
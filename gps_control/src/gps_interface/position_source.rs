@@ -0,0 +1,15 @@
+use futures::future::BoxFuture;
+
+use super::gps_interface::ReceiverState;
+
+pub type SourceResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// Where the receiver data comes from: the gpsd daemon, or the receiver's own serial port.
+pub trait PositionSource: Send {
+    /// Name of the source for the log messages, for example "the GPSD daemon at 127.0.0.1:2947".
+    fn describe(&self) -> String;
+
+    /// Connect to the source and read from it until the connection is lost, updating the receiver state as
+    /// the messages come in. Returns Ok when the source closes the connection.
+    fn run<'a>(&'a mut self, state: &'a mut ReceiverState) -> BoxFuture<'a, SourceResult>;
+}
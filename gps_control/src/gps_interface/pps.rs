@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of pulses the offset statistics are worked out over.
+const OFFSET_WINDOW: usize = 64;
+/// The PPS is reported missing when no pulse arrives for this long.
+const PPS_TIMEOUT: Duration = Duration::from_millis(2500);
+
+/// PPS report from gpsd. gpsd_proto decodes the times as f32, which can't hold the seconds since the epoch
+/// to better than a few minutes, so the report is decoded here with integers.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PpsReport {
+    #[serde(default)]
+    pub device: String,
+    pub real_sec: i64,
+    pub real_nsec: i64,
+    pub clock_sec: i64,
+    pub clock_nsec: i64,
+    pub precision: Option<i32>,
+}
+
+impl PpsReport {
+    /// Offset of the system clock from the PPS time, in ns.
+    fn offset_ns(&self) -> i64 {
+        (self.clock_sec - self.real_sec) * 1_000_000_000 + (self.clock_nsec - self.real_nsec)
+    }
+}
+
+/// PPS timing quality, returned by /api/time and sent on the web socket.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct TimeData {
+    pub device: String,
+    /// A pulse has been received within the PPS timeout.
+    pub pps_present: bool,
+    pub pulses: u64,
+    /// Pulses missing between the received ones, from gaps in the PPS seconds.
+    pub missed_pulses: u64,
+    pub seconds_since_pulse: Option<f64>,
+    /// Offsets of the system clock from the PPS, in ns, over the last pulses.
+    pub last_offset_ns: Option<i64>,
+    pub mean_offset_ns: Option<f64>,
+    pub min_offset_ns: Option<i64>,
+    pub max_offset_ns: Option<i64>,
+    /// Standard deviation of the offsets.
+    pub jitter_ns: Option<f64>,
+    /// NTP style precision reported by gpsd (log2 seconds).
+    pub precision: Option<i32>,
+}
+
+/// Keeps track of the PPS pulses reported by gpsd.
+#[derive(Default)]
+pub struct PpsMonitor {
+    device: String,
+    offsets: VecDeque<i64>,
+    last_pulse: Option<(i64, Instant)>,
+    pulses: u64,
+    missed_pulses: u64,
+    precision: Option<i32>,
+    present: bool,
+}
+
+impl PpsMonitor {
+    pub fn add_pulse(&mut self, report: &PpsReport, now: Instant) {
+        if let Some((last_sec, _)) = self.last_pulse {
+            let gap = report.real_sec - last_sec;
+            if gap > 1 {
+                log::warn!("Missed {} PPS pulses.", gap - 1);
+                self.missed_pulses += (gap - 1) as u64;
+            }
+        }
+        if !self.present {
+            log::info!("Receiving PPS from {}.", report.device);
+        }
+
+        if self.offsets.len() == OFFSET_WINDOW {
+            self.offsets.pop_front();
+        }
+        self.offsets.push_back(report.offset_ns());
+        self.device = report.device.clone();
+        self.last_pulse = Some((report.real_sec, now));
+        self.precision = report.precision;
+        self.pulses += 1;
+        self.present = true;
+    }
+
+    /// Check whether the pulses have stopped, returns true when the PPS has just gone missing.
+    pub fn check_lost(&mut self, now: Instant) -> bool {
+        let timed_out = match self.last_pulse {
+            Some((_, time)) => now.duration_since(time) > PPS_TIMEOUT,
+            None => true,
+        };
+        if self.present && timed_out {
+            log::warn!("No PPS pulse for {} ms.", PPS_TIMEOUT.as_millis());
+            self.present = false;
+            return true;
+        }
+        false
+    }
+
+    pub fn data(&self, now: Instant) -> TimeData {
+        let count = self.offsets.len() as f64;
+        let mean = if self.offsets.is_empty() { None } else { Some(self.offsets.iter().sum::<i64>() as f64 / count) };
+        let jitter = mean.map(|mean| (self.offsets.iter().map(|offset| (*offset as f64 - mean).powi(2)).sum::<f64>() / count).sqrt());
+        TimeData {
+            device: self.device.clone(),
+            pps_present: self.present,
+            pulses: self.pulses,
+            missed_pulses: self.missed_pulses,
+            seconds_since_pulse: self.last_pulse.map(|(_, time)| now.duration_since(time).as_secs_f64()),
+            last_offset_ns: self.offsets.back().cloned(),
+            mean_offset_ns: mean,
+            min_offset_ns: self.offsets.iter().min().cloned(),
+            max_offset_ns: self.offsets.iter().max().cloned(),
+            jitter_ns: jitter,
+            precision: self.precision,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn pulse(real_sec: i64, offset_ns: i64) -> PpsReport {
+        PpsReport {
+            device: "/dev/pps0".to_string(),
+            real_sec: real_sec,
+            real_nsec: 0,
+            clock_sec: real_sec,
+            clock_nsec: offset_ns,
+            precision: Some(-20),
+        }
+    }
+
+    #[test]
+    fn test_offset_statistics () {
+        let mut monitor = PpsMonitor::default();
+        let now = Instant::now();
+        monitor.add_pulse(&pulse(1_700_000_000, 100), now);
+        monitor.add_pulse(&pulse(1_700_000_001, 300), now);
+        let data = monitor.data(now);
+        assert_eq! (data.pulses, 2);
+        assert_eq! (data.last_offset_ns, Some(300));
+        assert_eq! (data.mean_offset_ns, Some(200.));
+        assert_eq! (data.jitter_ns, Some(100.));
+        assert_eq! (data.missed_pulses, 0);
+    }
+
+    #[test]
+    fn test_negative_offset () {
+        let report = PpsReport { clock_sec: 1_699_999_999, clock_nsec: 999_999_900, ..pulse(1_700_000_000, 0) };
+        assert_eq! (report.offset_ns(), -100);
+    }
+
+    #[test]
+    fn test_missing_pulses () {
+        let mut monitor = PpsMonitor::default();
+        let now = Instant::now();
+        monitor.add_pulse(&pulse(1_700_000_000, 0), now);
+        monitor.add_pulse(&pulse(1_700_000_004, 0), now);
+        assert_eq! (monitor.data(now).missed_pulses, 3);
+
+        assert! (!monitor.check_lost(now + Duration::from_secs(1)));
+        assert! (monitor.check_lost(now + Duration::from_secs(3)));
+        assert! (!monitor.check_lost(now + Duration::from_secs(4)));
+        assert! (!monitor.data(now).pps_present);
+    }
+}
@@ -0,0 +1,490 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, BytesMut};
+use futures::future::BoxFuture;
+use gpsd_proto::Mode;
+use tokio_serial::SerialPortBuilderExt;
+use tokio_util::codec::{Decoder, FramedRead};
+
+use super::chrony::LeapIndicator;
+use super::gps_interface::{DeviceState, FixQuality, GPSData, ReceiverState};
+use super::position_source::{PositionSource, SourceResult};
+use super::survey::SurveyStatus;
+use super::ubx::{self, NavHpPosLlh, NavPvt, NavStatus, NavTimeLs};
+
+const UBX_SYNC: [u8; 2] = [0xb5, 0x62];
+/// UBX header (sync, class, id and length) and checksum sizes.
+const UBX_HEADER_LENGTH: usize = 6;
+const UBX_CHECKSUM_LENGTH: usize = 2;
+/// Longest UBX payload accepted, a longer length is taken to be a corrupted header.
+const MAX_UBX_LENGTH: usize = 8192;
+/// NMEA sentences are at most 82 characters, but the u-blox high precision ones run a little longer.
+const MAX_NMEA_LENGTH: usize = 128;
+const KNOTS_TO_M_PER_S: f32 = 0.514444;
+/// Longest wait for the serial source to close the port before ubxtool is run on it anyway.
+const PORT_HANDOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A message read from the receiver.
+#[derive(Debug, PartialEq)]
+pub enum ReceiverMessage {
+    Ubx { class: u8, id: u8, payload: Vec<u8> },
+    /// NMEA sentence without the leading $ and the checksum, for example "GNGGA,123519,...".
+    Nmea(String),
+}
+
+/// UBX 8 bit Fletcher checksum over the class, id, length and payload.
+fn ubx_checksum(data: &[u8]) -> [u8; 2] {
+    let mut checksum = [0u8; 2];
+    for byte in data {
+        checksum[0] = checksum[0].wrapping_add(*byte);
+        checksum[1] = checksum[1].wrapping_add(checksum[0]);
+    }
+    checksum
+}
+
+/// Check the NMEA checksum of a line, returning the sentence between the $ and the *.
+fn nmea_sentence(line: &[u8]) -> Option<String> {
+    let line = std::str::from_utf8(line).ok()?.trim_end();
+    let (sentence, checksum) = line.strip_prefix('$')?.split_once('*')?;
+    let expected = u8::from_str_radix(checksum, 16).ok()?;
+    if sentence.bytes().fold(0, |checksum, byte| checksum ^ byte) == expected {
+        Some(sentence.to_string())
+    } else {
+        None
+    }
+}
+
+/// Splits the receiver output into the UBX and NMEA messages, skipping anything that doesn't check out.
+#[derive(Default)]
+pub struct ReceiverCodec;
+
+impl Decoder for ReceiverCodec {
+    type Item = ReceiverMessage;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            //Skip to the start of the next message.
+            match buffer.iter().position(|byte| *byte == UBX_SYNC[0] || *byte == b'$') {
+                Some(start) => buffer.advance(start),
+                None => {
+                    buffer.clear();
+                    return Ok(None);
+                }
+            }
+
+            if buffer[0] == b'$' {
+                match buffer.iter().position(|byte| *byte == b'\n') {
+                    Some(end) => match nmea_sentence(&buffer[..end]) {
+                        Some(sentence) => {
+                            buffer.advance(end + 1);
+                            return Ok(Some(ReceiverMessage::Nmea(sentence)));
+                        },
+                        None => buffer.advance(1),
+                    },
+                    None if buffer.len() > MAX_NMEA_LENGTH => buffer.advance(1),
+                    None => return Ok(None),
+                }
+            } else {
+                if buffer.len() < UBX_HEADER_LENGTH {
+                    return Ok(None);
+                }
+                let length = u16::from_le_bytes([buffer[4], buffer[5]]) as usize;
+                if buffer[1] != UBX_SYNC[1] || length > MAX_UBX_LENGTH {
+                    buffer.advance(1);
+                    continue;
+                }
+                let frame_length = UBX_HEADER_LENGTH + length + UBX_CHECKSUM_LENGTH;
+                if buffer.len() < frame_length {
+                    buffer.reserve(frame_length - buffer.len());
+                    return Ok(None);
+                }
+                if ubx_checksum(&buffer[2..UBX_HEADER_LENGTH + length]) != buffer[UBX_HEADER_LENGTH + length..frame_length] {
+                    log::debug!("Dropped a UBX message with a bad checksum.");
+                    buffer.advance(1);
+                    continue;
+                }
+                let frame = buffer.split_to(frame_length);
+                return Ok(Some(ReceiverMessage::Ubx {
+                    class: frame[2],
+                    id: frame[3],
+                    payload: frame[UBX_HEADER_LENGTH..UBX_HEADER_LENGTH + length].to_vec(),
+                }));
+            }
+        }
+    }
+}
+
+/// NMEA latitude or longitude (d)ddmm.mmmm with its hemisphere, in degrees.
+fn nmea_coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let value: f64 = value.parse().ok()?;
+    let degrees = (value / 100.).floor();
+    let coordinate = degrees + (value - degrees * 100.) / 60.;
+    match hemisphere {
+        "S" | "W" => Some(-coordinate),
+        _ => Some(coordinate),
+    }
+}
+
+/// gpsd style mode and status (2 DGPS, 3 RTK fixed, 4 RTK float) from the GGA fix quality. Dead reckoning (6) isn't
+/// a GNSS fix.
+fn gga_status(quality: &str) -> (Mode, i32) {
+    match quality {
+        "1" | "3" => (Mode::Fix3d, 1),
+        "2" => (Mode::Fix3d, 2),
+        "4" => (Mode::Fix3d, 3),
+        "5" => (Mode::Fix3d, 4),
+        _ => (Mode::NoFix, 0),
+    }
+}
+
+/// Fill in the position from a GGA sentence.
+fn update_from_gga(gps: &mut GPSData, fields: &[&str]) {
+    let (mode, status) = gga_status(fields[6]);
+    gps.fix = FixQuality::from_tpv(&mode, Some(status));
+    gps.status = status;
+    gps.lat = nmea_coordinate(fields[2], fields[3]).unwrap_or(0.);
+    gps.lon = nmea_coordinate(fields[4], fields[5]).unwrap_or(0.);
+    gps.alt = fields[9].parse().unwrap_or(0.);
+    gps.geoid_sep = fields[11].parse().ok();
+    gps.dgps_age = fields[13].parse().ok();
+    gps.dgps_station = fields[14].parse().ok();
+}
+
+/// Fill in the time, speed and track from an RMC sentence.
+fn update_from_rmc(gps: &mut GPSData, fields: &[&str]) {
+    let (time, date) = (fields[1], fields[9]);
+    if time.len() >= 6 && date.len() == 6 {
+        gps.time = format!("20{}-{}-{}T{}:{}:{}Z", &date[4..6], &date[2..4], &date[0..2], &time[0..2], &time[2..4], &time[4..]);
+    }
+    gps.speed = fields[7].parse::<f32>().map_or(0., |knots| knots * KNOTS_TO_M_PER_S);
+    gps.track = fields[8].parse().unwrap_or(0.);
+}
+
+/// Fill in the error ellipse and the position error sigmas from a GST sentence.
+fn update_from_gst(gps: &mut GPSData, fields: &[&str]) {
+    gps.rms = fields[2].parse().unwrap_or(0.);
+    gps.major = fields[3].parse().unwrap_or(0.);
+    gps.minor = fields[4].parse().unwrap_or(0.);
+    gps.orient = fields[5].parse().unwrap_or(0.);
+    gps.lat_err = fields[6].parse().ok();
+    gps.lon_err = fields[7].parse().ok();
+    gps.alt_err = fields[8].parse().ok();
+}
+
+#[derive(Default)]
+struct PortState {
+    /// The GPS control wants the port for ubxtool.
+    requested: bool,
+    /// The serial source has the port open.
+    open: bool,
+}
+
+#[derive(Default)]
+struct PortLockInner {
+    state: Mutex<PortState>,
+    /// Wakes the GPS control once the source has closed the port.
+    closed: Condvar,
+    /// Wakes the source when the port is requested or given back.
+    changed: tokio::sync::Notify,
+}
+
+/// Hands the receiver port over from the serial source to ubxtool while the GPS control configures the receiver, so
+/// the acknowledgements are read by ubxtool rather than by the source.
+#[derive(Clone, Default)]
+pub struct PortLock {
+    inner: Arc<PortLockInner>,
+}
+
+/// The port is held for ubxtool until this is dropped.
+pub struct PortGuard {
+    lock: PortLock,
+}
+
+impl PortLock {
+    /// Take the port from the serial source, blocking until the source has closed it. The source has to run on
+    /// another thread than the caller.
+    pub fn take(&self) -> PortGuard {
+        let mut state = self.inner.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.requested = true;
+        self.inner.changed.notify_one();
+        let (state, timeout) = self.inner.closed.wait_timeout_while(state, PORT_HANDOVER_TIMEOUT, |state| state.open)
+            .unwrap_or_else(|e| e.into_inner());
+        if timeout.timed_out() && state.open {
+            log::warn!("The serial source didn't close the receiver port in time.");
+        }
+        PortGuard { lock: self.clone() }
+    }
+
+    /// Wait until the port isn't held for ubxtool, then mark it as open by the source.
+    async fn open(&self) {
+        loop {
+            let changed = self.inner.changed.notified();
+            {
+                let mut state = self.inner.state.lock().unwrap_or_else(|e| e.into_inner());
+                if !state.requested {
+                    state.open = true;
+                    return;
+                }
+            }
+            changed.await;
+        }
+    }
+
+    /// Wait until the GPS control asks for the port.
+    async fn requested(&self) {
+        loop {
+            let changed = self.inner.changed.notified();
+            if self.inner.state.lock().unwrap_or_else(|e| e.into_inner()).requested {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// The source has closed the port.
+    fn close(&self) {
+        self.inner.state.lock().unwrap_or_else(|e| e.into_inner()).open = false;
+        self.inner.closed.notify_all();
+    }
+}
+
+impl Drop for PortGuard {
+    fn drop(&mut self) {
+        self.lock.inner.state.lock().unwrap_or_else(|e| e.into_inner()).requested = false;
+        self.lock.inner.changed.notify_one();
+    }
+}
+
+/// Reads the receiver straight from its serial (or USB) port, without gpsd.
+pub struct SerialSource {
+    path: String,
+    baud_rate: u32,
+    /// Closes the port while the GPS control runs ubxtool on it.
+    port_lock: PortLock,
+    /// The receiver is sending NAV-PVT, so the less precise NMEA positions are ignored.
+    ubx_navigation: bool,
+    /// Leap second warning from the last NAV-TIMELS, sent to chrony with the time of each NAV-PVT.
+    leap: LeapIndicator,
+}
+
+impl SerialSource {
+    pub fn new(path: &str, baud_rate: u32, port_lock: PortLock) -> Self {
+        SerialSource {
+            path: path.to_string(),
+            baud_rate: baud_rate,
+            port_lock: port_lock,
+            ubx_navigation: false,
+            leap: LeapIndicator::None,
+        }
+    }
+
+    /// Read the receiver until the port is closed, or is asked for by the GPS control (returning true).
+    async fn read_port(&mut self, state: &mut ReceiverState) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let port = tokio_serial::new(&self.path, self.baud_rate).open_native_async()?;
+        let mut framed = FramedRead::new(port, ReceiverCodec);
+
+        log::info!("Reading the receiver at {} directly.", self.path);
+        state.connected();
+        state.update_device(DeviceState { path: self.path.clone(), driver: "serial".to_string(), receiver_active: true }, "".to_string());
+        loop {
+            tokio::select! {
+                message = state.next(&mut framed) => match message {
+                    Some(message) => {
+                        let received = SystemTime::now();
+                        self.handle_message(message?, received, state);
+                    },
+                    None => return Ok(false),
+                },
+                _ = self.port_lock.requested() => return Ok(true),
+            }
+        }
+    }
+
+    /// Handle a message, received at the given system time.
+    fn handle_message(&mut self, message: ReceiverMessage, received: SystemTime, state: &mut ReceiverState) {
+        match message {
+            ReceiverMessage::Nmea(sentence) => self.handle_nmea(&sentence, state),
+            ReceiverMessage::Ubx { class, id, payload } => self.handle_ubx(class, id, &payload, received, state),
+        }
+    }
+
+    /// Update the GPS data from a UBX navigation message. The data is sent on with each NAV-PVT and the
+    /// NAV-HPPOSLLH following it, NAV-STATUS coming before them in the epoch. The time of each NAV-PVT is passed
+    /// on to chrony with the time it was received at.
+    fn handle_ubx(&mut self, class: u8, id: u8, payload: &[u8], received: SystemTime, state: &mut ReceiverState) {
+        let gps = state.device_data(Some(self.path.clone()));
+        match (class, id) {
+            (ubx::CLASS_NAV, ubx::ID_NAV_PVT) => match NavPvt::parse(payload) {
+                Some(pvt) => {
+                    if !self.ubx_navigation {
+                        log::info!("Receiving UBX navigation solutions from {}.", self.path);
+                        self.ubx_navigation = true;
+                    }
+                    pvt.update(gps);
+                    state.position_received(&self.path);
+                    if let Some((real_sec, real_nsec)) = pvt.unix_time() {
+                        state.time_received(received, real_sec, real_nsec, self.leap);
+                    }
+                },
+                None => log::warn!("Failed to decode the NAV-PVT message."),
+            },
+            (ubx::CLASS_NAV, ubx::ID_NAV_HPPOSLLH) => {
+                if let Some(position) = NavHpPosLlh::parse(payload) {
+                    position.update(gps);
+                    state.send_data(&self.path);
+                }
+            },
+            (ubx::CLASS_NAV, ubx::ID_NAV_STATUS) => {
+                if let Some(status) = NavStatus::parse(payload) {
+                    status.update(gps);
+                }
+            },
+            (ubx::CLASS_NAV, ubx::ID_NAV_TIMELS) => match NavTimeLs::parse(payload) {
+                Some(leap_seconds) => {
+                    let leap = leap_seconds.leap_indicator();
+                    if leap != self.leap {
+                        log::info!("Leap second warning from the receiver: {:?}.", leap);
+                        self.leap = leap;
+                    }
+                },
+                None => log::warn!("Failed to decode the NAV-TIMELS message."),
+            },
+            (ubx::CLASS_NAV, ubx::ID_NAV_SVIN) => match SurveyStatus::from_payload(payload) {
+                Some(survey) => state.survey_received(survey),
+                None => log::warn!("Failed to decode the NAV-SVIN message."),
+            },
+            _ => log::debug!("UBX message {:#04x} {:#04x}", class, id),
+        }
+    }
+
+    /// Update the GPS data from an NMEA sentence. The data is sent on with each GGA (position) and GST, the RMC
+    /// coming before them in the epoch.
+    fn handle_nmea(&mut self, sentence: &str, state: &mut ReceiverState) {
+        let fields: Vec<&str> = sentence.split(',').collect();
+        let gps = state.device_data(Some(self.path.clone()));
+        //Skip the talker id (GP, GN, ...).
+        match fields[0].get(2..) {
+            Some("GGA") | Some("RMC") if self.ubx_navigation => {},
+            Some("GGA") if fields.len() >= 15 => {
+                update_from_gga(gps, &fields);
+                state.position_received(&self.path);
+            },
+            Some("RMC") if fields.len() >= 10 => update_from_rmc(gps, &fields),
+            Some("GST") if fields.len() >= 9 => {
+                update_from_gst(gps, &fields);
+                state.send_data(&self.path);
+            },
+            _ => {}
+        }
+    }
+}
+
+impl PositionSource for SerialSource {
+    fn describe(&self) -> String {
+        format!("the receiver at {}", self.path)
+    }
+
+    fn run<'a>(&'a mut self, state: &'a mut ReceiverState) -> BoxFuture<'a, SourceResult> {
+        Box::pin(async move {
+            loop {
+                self.port_lock.open().await;
+                let result = self.read_port(state).await;
+                self.port_lock.close();
+                match result {
+                    Ok(true) => log::info!("Handed the receiver at {} over to ubxtool.", self.path),
+                    Ok(false) => return Ok(()),
+                    Err(e) => return Err(e),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn ubx_frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![UBX_SYNC[0], UBX_SYNC[1], class, id];
+        frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        frame.extend_from_slice(payload);
+        let checksum = ubx_checksum(&frame[2..]);
+        frame.extend_from_slice(&checksum);
+        frame
+    }
+
+    #[test]
+    fn test_decode_messages () {
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(b"\x00garbage$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n");
+        buffer.extend_from_slice(&ubx_frame(0x01, 0x07, &[1, 2, 3]));
+        buffer.extend_from_slice(b"$GPGGA,bad*00\r\n");
+        let mut bad_frame = ubx_frame(0x01, 0x03, &[4]);
+        bad_frame[6] = 5;
+        buffer.extend_from_slice(&bad_frame);
+        let partial = ubx_frame(0x01, 0x14, &[6, 7]);
+        buffer.extend_from_slice(&partial[..5]);
+
+        let mut codec = ReceiverCodec;
+        assert_eq! (codec.decode(&mut buffer).unwrap(),
+                    Some(ReceiverMessage::Nmea("GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,".to_string())));
+        assert_eq! (codec.decode(&mut buffer).unwrap(), Some(ReceiverMessage::Ubx { class: 0x01, id: 0x07, payload: vec![1, 2, 3] }));
+        assert_eq! (codec.decode(&mut buffer).unwrap(), None);
+
+        buffer.extend_from_slice(&partial[5..]);
+        assert_eq! (codec.decode(&mut buffer).unwrap(), Some(ReceiverMessage::Ubx { class: 0x01, id: 0x14, payload: vec![6, 7] }));
+        assert! (buffer.is_empty());
+    }
+
+    #[test]
+    fn test_gga () {
+        let mut gps = GPSData::new("/dev/ttyACM0");
+        let sentence = "GNGGA,123519.00,4807.03800,S,01131.00000,W,4,12,0.6,545.4,M,46.9,M,1.0,0042";
+        update_from_gga(&mut gps, &sentence.split(',').collect::<Vec<_>>());
+        assert_eq! (gps.fix, FixQuality::RTKFixed);
+        assert! ((gps.lat + 48.1173).abs() < 1e-9);
+        assert! ((gps.lon + 11.516666666).abs() < 1e-6);
+        assert_eq! (gps.alt, 545.4);
+        assert_eq! (gps.dgps_station, Some(42));
+    }
+
+    #[test]
+    fn test_rmc () {
+        let mut gps = GPSData::new("/dev/ttyACM0");
+        let sentence = "GNRMC,123519.00,A,4807.038,N,01131.000,E,10.0,84.4,230324,003.1,W,D";
+        update_from_rmc(&mut gps, &sentence.split(',').collect::<Vec<_>>());
+        assert_eq! (gps.time, "2024-03-23T12:35:19.00Z");
+        assert! ((gps.speed - 5.14444).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_gst () {
+        let mut gps = GPSData::new("/dev/ttyACM0");
+        gps.lat = 48.1173;
+        let sentence = "GNGST,123519.00,0.012,0.010,0.008,45.0,0.011,0.009,0.020";
+        update_from_gst(&mut gps, &sentence.split(',').collect::<Vec<_>>());
+        assert_eq! (gps.lat, 48.1173);
+        assert_eq! ((gps.lat_err, gps.lon_err, gps.alt_err), (Some(0.011), Some(0.009), Some(0.020)));
+        assert_eq! (gps.major, 0.010);
+    }
+
+    #[test]
+    fn test_port_lock () {
+        use futures::FutureExt;
+
+        let lock = PortLock::default();
+        assert! (lock.open().now_or_never().is_some());
+        assert! (lock.requested().now_or_never().is_none());
+        lock.close();
+
+        //The port is closed, so it's taken without waiting and the source can't open it until it's given back.
+        let guard = lock.take();
+        assert! (lock.requested().now_or_never().is_some());
+        assert! (lock.open().now_or_never().is_none());
+        drop(guard);
+        assert! (lock.open().now_or_never().is_some());
+    }
+}
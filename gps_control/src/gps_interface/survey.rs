@@ -0,0 +1,108 @@
+use serde::Serialize;
+
+const NAV_SVIN_LENGTH: usize = 40;
+
+/// Progress of the base station survey-in, from UBX-NAV-SVIN. Returned by /api/base/survey and sent on the web socket.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SurveyStatus {
+    /// Seconds the survey has been running for, and the number of positions averaged.
+    pub duration_seconds: u32,
+    pub observations: u32,
+    /// Mean ECEF position so far, in m.
+    pub mean_ecef_x: f64,
+    pub mean_ecef_y: f64,
+    pub mean_ecef_z: f64,
+    /// Current 3D accuracy of the mean position, in m.
+    pub accuracy: f64,
+    /// The survey is running.
+    pub active: bool,
+    /// Both the minimum duration and the accuracy limit have been met, so the position is fixed.
+    pub valid: bool,
+}
+
+impl SurveyStatus {
+    /// Work out the status from the mean position in cm and its high precision part in 0.1 mm, and the accuracy in 0.1 mm.
+    fn new(duration_seconds: u32, observations: u32, mean: [i64; 3], mean_hp: [i64; 3], accuracy: u32, active: bool, valid: bool) -> Self {
+        let position = |axis: usize| mean[axis] as f64 * 1e-2 + mean_hp[axis] as f64 * 1e-4;
+        SurveyStatus {
+            duration_seconds: duration_seconds,
+            observations: observations,
+            mean_ecef_x: position(0),
+            mean_ecef_y: position(1),
+            mean_ecef_z: position(2),
+            accuracy: accuracy as f64 * 1e-4,
+            active: active,
+            valid: valid,
+        }
+    }
+
+    /// Decode the NAV-SVIN payload, as read straight from the receiver.
+    pub fn from_payload(payload: &[u8]) -> Option<Self> {
+        if payload.len() < NAV_SVIN_LENGTH {
+            return None;
+        }
+        let u32_at = |offset: usize| u32::from_le_bytes(payload[offset..offset + 4].try_into().unwrap());
+        let i32_at = |offset: usize| i32::from_le_bytes(payload[offset..offset + 4].try_into().unwrap()) as i64;
+        let i8_at = |offset: usize| payload[offset] as i8 as i64;
+        Some(SurveyStatus::new(u32_at(8), u32_at(32),
+                               [i32_at(12), i32_at(16), i32_at(20)],
+                               [i8_at(24), i8_at(25), i8_at(26)],
+                               u32_at(28), payload[37] != 0, payload[36] != 0))
+    }
+
+    /// Read the status from the NAV-SVIN decoded by ubxtool, for example:
+    /// "UBX-NAV-SVIN:\n  version 0 reserved1[0 0 0]  iTOW 1000 dur 60\n  meanX 91850212 meanY ..."
+    pub fn from_ubxtool(output: &str) -> Option<Self> {
+        let start = output.find("UBX-NAV-SVIN:")?;
+        let message = &output[start + "UBX-NAV-SVIN:".len()..];
+        //Stop at the next message ubxtool printed.
+        let message = &message[..message.find("UBX-").unwrap_or(message.len())];
+        let words: Vec<&str> = message.split_whitespace().collect();
+        let value = |name: &str| -> Option<i64> {
+            words.windows(2).find(|pair| pair[0] == name).and_then(|pair| pair[1].parse().ok())
+        };
+        Some(SurveyStatus::new(value("dur")? as u32, value("obs")? as u32,
+                               [value("meanX")?, value("meanY")?, value("meanZ")?],
+                               [value("meanXHP")?, value("meanYHP")?, value("meanZHP")?],
+                               value("meanAcc")? as u32, value("active")? != 0, value("valid")? != 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_from_payload () {
+        let mut payload = vec![0u8; NAV_SVIN_LENGTH];
+        payload[8..12].copy_from_slice(&600u32.to_le_bytes());
+        payload[12..16].copy_from_slice(&91_850_212i32.to_le_bytes());
+        payload[16..20].copy_from_slice(&(-434_627_133i32).to_le_bytes());
+        payload[20..24].copy_from_slice(&456_113_106i32.to_le_bytes());
+        payload[24] = 35;
+        payload[25] = (-12i8) as u8;
+        payload[28..32].copy_from_slice(&18_000u32.to_le_bytes());
+        payload[32..36].copy_from_slice(&600u32.to_le_bytes());
+        payload[37] = 1;
+
+        let status = SurveyStatus::from_payload(&payload).unwrap();
+        assert! ((status.mean_ecef_x - 918_502.1235).abs() < 1e-6);
+        assert! ((status.mean_ecef_y + 4_346_271.3312).abs() < 1e-6);
+        assert! ((status.accuracy - 1.8).abs() < 1e-9);
+        assert_eq! (status.observations, 600);
+        assert! (status.active && !status.valid);
+    }
+
+    #[test]
+    fn test_from_ubxtool () {
+        let output = "UBX-ACK-ACK:\n  ACK to UBX-NAV-SVIN\n\nUBX-NAV-SVIN:\n  version 0 reserved1[0 0 0]  iTOW 432000000 dur 7200\n  \
+                      meanX 91850212 meanY -434627133 meanZ 456113106\n  meanXHP 35 meanYHP -12 meanZHP 0 reserved2 0 meanAcc 14500\n  \
+                      obs 7200 valid 1 active 0 reserved3 0 0\n\nUBX-NAV-PVT:\n  dur 5\n";
+        let status = SurveyStatus::from_ubxtool(output).unwrap();
+        assert_eq! (status.duration_seconds, 7200);
+        assert! ((status.mean_ecef_z - 4_561_131.06).abs() < 1e-6);
+        assert! (status.valid && !status.active);
+        assert! (SurveyStatus::from_ubxtool("UBX-ACK-NAK:\n").is_none());
+    }
+}
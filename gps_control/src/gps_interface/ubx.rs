@@ -0,0 +1,444 @@
+use gpsd_proto::Mode;
+
+use super::chrony::LeapIndicator;
+use super::gps_interface::{FixQuality, GPSData};
+
+pub const CLASS_NAV: u8 = 0x01;
+pub const ID_NAV_STATUS: u8 = 0x03;
+pub const ID_NAV_PVT: u8 = 0x07;
+pub const ID_NAV_HPPOSLLH: u8 = 0x14;
+pub const ID_NAV_TIMELS: u8 = 0x26;
+pub const ID_NAV_SVIN: u8 = 0x3b;
+
+const NAV_PVT_LENGTH: usize = 92;
+const NAV_HPPOSLLH_LENGTH: usize = 36;
+const NAV_STATUS_LENGTH: usize = 16;
+const NAV_TIMELS_LENGTH: usize = 24;
+/// Seconds in a day, the leap second warning is given on the day of the leap second.
+const SECONDS_PER_DAY: i32 = 86400;
+
+fn u16_at(payload: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([payload[offset], payload[offset + 1]])
+}
+
+fn u32_at(payload: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(payload[offset..offset + 4].try_into().unwrap())
+}
+
+fn i32_at(payload: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(payload[offset..offset + 4].try_into().unwrap())
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Split a CFG-TMODE position value into its standard part and its high precision part, in 1/100 of the standard
+/// unit (cm and 0.1 mm, or 1e-7 and 1e-9 degrees). Both parts have the sign of the value, so the HP part is -99 to 99.
+pub fn split_high_precision(value: f64) -> (i64, i64) {
+    let total = (value * 100.0).round() as i64;
+    (total / 100, total % 100)
+}
+
+/// Carrier phase range solution, from the flags of NAV-PVT and NAV-STATUS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CarrierSolution {
+    None,
+    Float,
+    Fixed,
+}
+
+impl CarrierSolution {
+    fn from_flags(flags: u8) -> Self {
+        match (flags >> 6) & 0x03 {
+            1 => CarrierSolution::Float,
+            2 => CarrierSolution::Fixed,
+            _ => CarrierSolution::None,
+        }
+    }
+}
+
+/// gpsd style mode and status (1 fix, 2 DGPS, 3 RTK fixed, 4 RTK float) from the UBX fix type (0 no fix,
+/// 1 dead reckoning, 2 2D, 3 3D, 4 GNSS and dead reckoning, 5 time only). Dead reckoning alone isn't a GNSS fix.
+fn fix_status(fix_type: u8, fix_ok: bool, differential: bool, carrier: CarrierSolution) -> (Mode, i32) {
+    let mode = match fix_type {
+        2 if fix_ok => Mode::Fix2d,
+        3 | 4 if fix_ok => Mode::Fix3d,
+        _ => return (Mode::NoFix, 0),
+    };
+    let status = match carrier {
+        CarrierSolution::Fixed => 3,
+        CarrierSolution::Float => 4,
+        CarrierSolution::None if differential => 2,
+        CarrierSolution::None => 1,
+    };
+    (mode, status)
+}
+
+/// UBX-NAV-PVT, the navigation solution of the epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavPvt {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub min: u8,
+    pub sec: u8,
+    /// The date and time are valid (validDate and validTime).
+    pub valid_time: bool,
+    /// The time of day is fully resolved, with no second ambiguity.
+    pub fully_resolved: bool,
+    /// Fraction of the second in ns, can be negative.
+    pub nano: i32,
+    pub fix_type: u8,
+    pub fix_ok: bool,
+    pub differential: bool,
+    pub carrier: CarrierSolution,
+    pub satellites_used: u8,
+    /// Degrees.
+    pub lon: f64,
+    pub lat: f64,
+    /// Height above the ellipsoid and above mean sea level, in m.
+    pub height: f64,
+    pub height_msl: f64,
+    /// Horizontal and vertical accuracy estimates, in m.
+    pub h_acc: f64,
+    pub v_acc: f64,
+    /// Velocity north, east and down, in m/s.
+    pub vel_n: f64,
+    pub vel_e: f64,
+    pub vel_d: f64,
+    /// Ground speed in m/s and heading of motion in degrees.
+    pub ground_speed: f64,
+    pub heading: f64,
+}
+
+impl NavPvt {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < NAV_PVT_LENGTH {
+            return None;
+        }
+        let valid = payload[11];
+        let flags = payload[21];
+        Some(NavPvt {
+            year: u16_at(payload, 4),
+            month: payload[6],
+            day: payload[7],
+            hour: payload[8],
+            min: payload[9],
+            sec: payload[10],
+            valid_time: valid & 0x03 == 0x03,
+            fully_resolved: valid & 0x04 != 0,
+            nano: i32_at(payload, 16),
+            fix_type: payload[20],
+            fix_ok: flags & 0x01 != 0,
+            differential: flags & 0x02 != 0,
+            carrier: CarrierSolution::from_flags(flags),
+            satellites_used: payload[23],
+            lon: i32_at(payload, 24) as f64 * 1e-7,
+            lat: i32_at(payload, 28) as f64 * 1e-7,
+            height: i32_at(payload, 32) as f64 * 1e-3,
+            height_msl: i32_at(payload, 36) as f64 * 1e-3,
+            h_acc: u32_at(payload, 40) as f64 * 1e-3,
+            v_acc: u32_at(payload, 44) as f64 * 1e-3,
+            vel_n: i32_at(payload, 48) as f64 * 1e-3,
+            vel_e: i32_at(payload, 52) as f64 * 1e-3,
+            vel_d: i32_at(payload, 56) as f64 * 1e-3,
+            ground_speed: i32_at(payload, 60) as f64 * 1e-3,
+            heading: i32_at(payload, 64) as f64 * 1e-5,
+        })
+    }
+
+    /// Time in the ISO 8601 form gpsd uses, None if the receiver doesn't know the time yet.
+    fn time(&self) -> Option<String> {
+        if !self.valid_time {
+            return None;
+        }
+        let seconds = (self.sec as f64 + self.nano as f64 * 1e-9).max(0.);
+        Some(format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:06.3}Z", self.year, self.month, self.day, self.hour, self.min, seconds))
+    }
+
+    /// UTC time of the solution as seconds and nanoseconds since the unix epoch, for chrony. None until the
+    /// time is valid and fully resolved.
+    pub fn unix_time(&self) -> Option<(i64, i64)> {
+        if !self.valid_time || !self.fully_resolved {
+            return None;
+        }
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let seconds = days * SECONDS_PER_DAY as i64 + self.hour as i64 * 3600 + self.min as i64 * 60 + self.sec as i64;
+        //The nanoseconds can be negative, rounding the time up to the next second.
+        if self.nano < 0 {
+            Some((seconds - 1, self.nano as i64 + 1_000_000_000))
+        } else {
+            Some((seconds, self.nano as i64))
+        }
+    }
+
+    pub fn update(&self, gps: &mut GPSData) {
+        let (mode, status) = fix_status(self.fix_type, self.fix_ok, self.differential, self.carrier);
+        gps.fix = FixQuality::from_tpv(&mode, Some(status));
+        gps.status = status;
+        gps.lat = self.lat;
+        gps.lon = self.lon;
+        gps.alt = self.height_msl;
+        gps.geoid_sep = Some((self.height - self.height_msl) as f32);
+        gps.track = self.heading as f32;
+        gps.speed = self.ground_speed as f32;
+        gps.climb = Some(-self.vel_d as f32);
+        if let Some(time) = self.time() {
+            gps.time = time;
+        }
+        gps.satellites_used = Some(self.satellites_used);
+        gps.h_acc = Some(self.h_acc);
+        gps.v_acc = Some(self.v_acc);
+        gps.vel_n = Some(self.vel_n);
+        gps.vel_e = Some(self.vel_e);
+        gps.vel_d = Some(self.vel_d);
+    }
+}
+
+/// UBX-NAV-HPPOSLLH, the position to 0.1 mm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavHpPosLlh {
+    /// Degrees.
+    pub lon: f64,
+    pub lat: f64,
+    /// Height above the ellipsoid and above mean sea level, in m.
+    pub height: f64,
+    pub height_msl: f64,
+    /// Horizontal and vertical accuracy estimates, in m.
+    pub h_acc: f64,
+    pub v_acc: f64,
+}
+
+impl NavHpPosLlh {
+    /// None for a short payload, or when the receiver flags the position as invalid.
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < NAV_HPPOSLLH_LENGTH || payload[3] & 0x01 != 0 {
+            return None;
+        }
+        //The high precision parts are signed bytes in 1e-9 degrees and 0.1 mm.
+        let high_precision = |offset: usize| payload[offset] as i8 as f64;
+        Some(NavHpPosLlh {
+            lon: i32_at(payload, 8) as f64 * 1e-7 + high_precision(24) * 1e-9,
+            lat: i32_at(payload, 12) as f64 * 1e-7 + high_precision(25) * 1e-9,
+            height: i32_at(payload, 16) as f64 * 1e-3 + high_precision(26) * 1e-4,
+            height_msl: i32_at(payload, 20) as f64 * 1e-3 + high_precision(27) * 1e-4,
+            h_acc: u32_at(payload, 28) as f64 * 1e-4,
+            v_acc: u32_at(payload, 32) as f64 * 1e-4,
+        })
+    }
+
+    pub fn update(&self, gps: &mut GPSData) {
+        gps.lat = self.lat;
+        gps.lon = self.lon;
+        gps.alt = self.height_msl;
+        gps.geoid_sep = Some((self.height - self.height_msl) as f32);
+        gps.h_acc = Some(self.h_acc);
+        gps.v_acc = Some(self.v_acc);
+    }
+}
+
+/// UBX-NAV-STATUS, the fix status and time to first fix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavStatus {
+    pub fix_type: u8,
+    pub fix_ok: bool,
+    pub differential: bool,
+    pub carrier: CarrierSolution,
+    /// Time to first fix, in s.
+    pub time_to_first_fix: f64,
+}
+
+impl NavStatus {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < NAV_STATUS_LENGTH {
+            return None;
+        }
+        let flags = payload[5];
+        Some(NavStatus {
+            fix_type: payload[4],
+            fix_ok: flags & 0x01 != 0,
+            differential: flags & 0x02 != 0,
+            carrier: CarrierSolution::from_flags(payload[7]),
+            time_to_first_fix: u32_at(payload, 8) as f64 * 1e-3,
+        })
+    }
+
+    pub fn update(&self, gps: &mut GPSData) {
+        let (mode, status) = fix_status(self.fix_type, self.fix_ok, self.differential, self.carrier);
+        gps.fix = FixQuality::from_tpv(&mode, Some(status));
+        gps.status = status;
+        gps.time_to_first_fix = Some(self.time_to_first_fix);
+    }
+}
+
+/// UBX-NAV-TIMELS, the leap second information.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavTimeLs {
+    /// The coming leap second: 1 inserted, -1 removed, 0 none.
+    pub leap_change: i8,
+    /// Seconds until the leap second takes effect, when the receiver knows it.
+    pub time_to_event: Option<i32>,
+}
+
+impl NavTimeLs {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < NAV_TIMELS_LENGTH {
+            return None;
+        }
+        let valid = payload[23];
+        Some(NavTimeLs {
+            leap_change: payload[11] as i8,
+            time_to_event: if valid & 0x02 != 0 { Some(i32_at(payload, 12)) } else { None },
+        })
+    }
+
+    /// Leap second warning for chrony, which is given during the day that ends with the leap second.
+    pub fn leap_indicator(&self) -> LeapIndicator {
+        match (self.leap_change, self.time_to_event) {
+            (1, Some(time)) if time > 0 && time <= SECONDS_PER_DAY => LeapIndicator::Insert,
+            (-1, Some(time)) if time > 0 && time <= SECONDS_PER_DAY => LeapIndicator::Delete,
+            _ => LeapIndicator::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_nav_pvt () {
+        let mut payload = vec![0u8; NAV_PVT_LENGTH];
+        payload[4..6].copy_from_slice(&2024u16.to_le_bytes());
+        payload[6..11].copy_from_slice(&[3, 23, 12, 35, 19]);
+        payload[11] = 0x07;
+        payload[16..20].copy_from_slice(&(-250_000_000i32).to_le_bytes());
+        payload[20] = 3;
+        payload[21] = 0x01 | 0x02 | 0x80;
+        payload[23] = 18;
+        payload[24..28].copy_from_slice(&(-1_151_666_667i32).to_le_bytes());
+        payload[28..32].copy_from_slice(&481_173_000i32.to_le_bytes());
+        payload[36..40].copy_from_slice(&545_400i32.to_le_bytes());
+        payload[40..44].copy_from_slice(&14u32.to_le_bytes());
+        payload[56..60].copy_from_slice(&(-120i32).to_le_bytes());
+
+        let pvt = NavPvt::parse(&payload).unwrap();
+        assert_eq! (pvt.carrier, CarrierSolution::Fixed);
+        let mut gps = GPSData::new("/dev/ttyACM0");
+        pvt.update(&mut gps);
+        assert_eq! (gps.fix, FixQuality::RTKFixed);
+        assert_eq! (gps.time, "2024-03-23T12:35:18.750Z");
+        assert! ((gps.lon + 115.1666667).abs() < 1e-9);
+        assert_eq! (gps.alt, 545.4);
+        assert_eq! (gps.h_acc, Some(0.014));
+        assert_eq! (gps.vel_d, Some(-0.12));
+        assert_eq! (gps.satellites_used, Some(18));
+        //2024-03-23T12:35:19 less 0.25 s.
+        assert_eq! (pvt.unix_time(), Some((1_711_197_318, 750_000_000)));
+        assert! (NavPvt::parse(&payload[..80]).is_none());
+
+        payload[11] = 0x03;
+        assert_eq! (NavPvt::parse(&payload).unwrap().unix_time(), None);
+    }
+
+    #[test]
+    fn test_nav_timels () {
+        let mut payload = vec![0u8; NAV_TIMELS_LENGTH];
+        payload[11] = 1;
+        payload[12..16].copy_from_slice(&3_600i32.to_le_bytes());
+        payload[23] = 0x03;
+        assert_eq! (NavTimeLs::parse(&payload).unwrap().leap_indicator(), LeapIndicator::Insert);
+
+        payload[11] = (-1i8) as u8;
+        assert_eq! (NavTimeLs::parse(&payload).unwrap().leap_indicator(), LeapIndicator::Delete);
+
+        //Announced for a later day, or without a known time to the event.
+        payload[12..16].copy_from_slice(&200_000i32.to_le_bytes());
+        assert_eq! (NavTimeLs::parse(&payload).unwrap().leap_indicator(), LeapIndicator::None);
+        payload[12..16].copy_from_slice(&3_600i32.to_le_bytes());
+        payload[23] = 0x01;
+        assert_eq! (NavTimeLs::parse(&payload).unwrap().leap_indicator(), LeapIndicator::None);
+        assert! (NavTimeLs::parse(&payload[..20]).is_none());
+    }
+
+    #[test]
+    fn test_nav_hpposllh () {
+        let mut payload = vec![0u8; NAV_HPPOSLLH_LENGTH];
+        payload[8..12].copy_from_slice(&(-1_151_666_667i32).to_le_bytes());
+        payload[12..16].copy_from_slice(&481_173_000i32.to_le_bytes());
+        payload[20..24].copy_from_slice(&545_400i32.to_le_bytes());
+        payload[24] = (-45i8) as u8;
+        payload[25] = 12;
+        payload[27] = 7;
+        payload[28..32].copy_from_slice(&142u32.to_le_bytes());
+
+        let position = NavHpPosLlh::parse(&payload).unwrap();
+        assert! ((position.lon + 115.166666745).abs() < 1e-12);
+        assert! ((position.lat - 48.117300012).abs() < 1e-12);
+        assert! ((position.height_msl - 545.4007).abs() < 1e-9);
+        assert! ((position.h_acc - 0.0142).abs() < 1e-12);
+
+        payload[3] = 0x01;
+        assert! (NavHpPosLlh::parse(&payload).is_none());
+    }
+
+    #[test]
+    fn test_split_high_precision () {
+        assert_eq! (split_high_precision(91_850_212.35), (91_850_212, 35));
+        assert_eq! (split_high_precision(-434_627_133.12), (-434_627_133, -12));
+        //0.29 * 100 is 28.999..., which used to be truncated to 28.
+        assert_eq! (split_high_precision(0.29), (0, 29));
+        assert_eq! (split_high_precision(12.999_6), (13, 0));
+        //Latitude of 45.123456789 degrees in 1e-7 degrees.
+        assert_eq! (split_high_precision(45.123_456_789 * 1e7), (451_234_567, 89));
+    }
+
+    #[test]
+    fn test_nav_status () {
+        let mut payload = vec![0u8; NAV_STATUS_LENGTH];
+        payload[4] = 3;
+        payload[5] = 0x03;
+        payload[7] = 0x40;
+        payload[8..12].copy_from_slice(&31_500u32.to_le_bytes());
+        let status = NavStatus::parse(&payload).unwrap();
+        let mut gps = GPSData::new("/dev/ttyACM0");
+        status.update(&mut gps);
+        assert_eq! (gps.fix, FixQuality::RTKFloat);
+        assert_eq! (gps.time_to_first_fix, Some(31.5));
+
+        payload[5] = 0;
+        NavStatus::parse(&payload).unwrap().update(&mut gps);
+        assert_eq! (gps.fix, FixQuality::NoFix);
+
+        //A 2D fix outside the accuracy limits isn't a fix either.
+        payload[4] = 2;
+        payload[7] = 0;
+        NavStatus::parse(&payload).unwrap().update(&mut gps);
+        assert_eq! (gps.fix, FixQuality::NoFix);
+        payload[5] = 0x01;
+        NavStatus::parse(&payload).unwrap().update(&mut gps);
+        assert_eq! (gps.fix, FixQuality::Fix2D);
+    }
+
+    #[test]
+    fn test_fix_status () {
+        let fix = |fix_type, fix_ok, differential, carrier| {
+            let (mode, status) = fix_status(fix_type, fix_ok, differential, carrier);
+            (FixQuality::from_tpv(&mode, Some(status)), status)
+        };
+        assert_eq! (fix(1, true, false, CarrierSolution::None), (FixQuality::NoFix, 0));
+        assert_eq! (fix(3, false, false, CarrierSolution::None), (FixQuality::NoFix, 0));
+        assert_eq! (fix(2, true, false, CarrierSolution::None), (FixQuality::Fix2D, 1));
+        assert_eq! (fix(4, true, true, CarrierSolution::None), (FixQuality::DGPS, 2));
+        assert_eq! (fix(3, true, true, CarrierSolution::Fixed), (FixQuality::RTKFixed, 3));
+    }
+}
@@ -1,31 +1,57 @@
 use actix::prelude::*;
 use actix_files::Files;
 //use tokio::sync::{broadcast, mpsc};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 
 mod web_socket;
 mod api;
+mod auth;
+mod error;
+mod geodesy;
 mod gps_interface;
 mod lora_streaming;
 mod settings;
+mod shutdown;
+mod status;
+mod supervisor;
 
-use settings::{Cli, Modes, SettingsHandler};
+use settings::{Cli, Modes, PositionSourceKind, SettingsHandler, StoredSettings};
 //use port_redirector::input_stream::InputSocket;
 //use port_redirector::retransmit_server::RetransmitServer;
-use gps_interface::gps_control::{GPS_DATA_DIR, GPSMode};
+use gps_interface::gps_control::{GPS_BAUDRATE, GPS_DATA_DIR, GPSMode};
+use gps_interface::position_source::PositionSource;
 
 //const STATIC_FILES: &str= "./static";
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let start_time = actix_web::web::Data::new(status::StartTime(std::time::Instant::now()));
     pretty_env_logger::init();
     log::info!("Starting UBlox GPS Control Software.");
 
     std::fs::create_dir_all(GPS_DATA_DIR)?;
-    let cli = Cli::parse();
+    let cli_matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&cli_matches).unwrap_or_else(|e| e.exit());
+
+    //Load the stored settings and layer the command line on top of them.
+    let settings_path = std::path::PathBuf::from(&cli.settings_file);
+    let secrets_path = cli.secrets_file.as_ref().map(std::path::PathBuf::from);
+    //A settings or secrets file that exists but can't be read stops the start up, rather than running with the
+    //default (open) authentication and overwriting the user's file on the next save.
+    let stored_settings = StoredSettings::load(&settings_path, secrets_path.as_deref(), cli.profile.as_deref(),
+                                               cli.mode.clone(), cli_matches.subcommand().map(|(_, m)| m)).map_err(|e| {
+        log::error!("Failed to load the settings, fix or remove the settings file to start: {}", e);
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+    })?;
+    let mode = stored_settings.resolve_site(&stored_settings.mode).unwrap_or_else(|e| {
+        log::error!("Starting without the fixed base position: {}", e);
+        stored_settings.mode.clone()
+    });
+    let authentication = actix_web::web::Data::new(auth::Authentication::new(stored_settings.auth.clone()));
+    if let Err(e) = mode.validate() {
+        log::warn!("Starting with invalid settings: {}", e);
+    }
 
-    //Setup the settings handler
-    let settings_handler = SettingsHandler::new(cli.mode.clone()).start();
 
     //Setup the serial port redirector
     //let input_serial_port = InputSocket::Serial {port_name: cli.gps_tty_port, baudrate: Some(115200), rd: None, tx: None};
@@ -45,35 +71,73 @@ async fn main() -> std::io::Result<()> {
     //tokio::spawn( async move { retransmit_server.run_loop().await; });
     
     let socket_monitor = web_socket::GPSWebSocketMonitor::new().start();
-    let gps_control = gps_interface::gps_control::GPSControl::new(Some(&cli.gpsd_server), Some(cli.gpsd_port), Some(cli.gps_usb_port), Some(cli.gps_tty_port)/*Some(cli.output_port)*/).start();
+    //The supervisor gets its own arbiter, so it keeps running while the GPS control blocks configuring the receiver.
+    let supervisor = supervisor::ProcessSupervisor::start_in_arbiter(&Arbiter::new().handle(), |_| supervisor::ProcessSupervisor::new());
+    let gps_control = gps_interface::gps_control::GPSControl::new(Some(&cli.gpsd_server), Some(cli.gpsd_port), cli.source, Some(cli.gps_usb_port.clone()), Some(cli.gps_tty_port)/*Some(cli.output_port)*/,
+                                                                   supervisor.clone(), socket_monitor.clone());
+    let port_lock = gps_control.port_lock();
+    let gps_control = gps_control.start();
+
+    //Setup the settings handler
+    let settings_handler = SettingsHandler::new(stored_settings, settings_path, secrets_path, gps_control.clone()).start();
     
-    let mut gps_interface = gps_interface::gps_interface::GPSInterface::new(Some(&cli.gpsd_server), Some(cli.gpsd_port), socket_monitor.clone());
+    let chrony_socket = match &cli.chrony_socket {
+        Some(path) => match gps_interface::chrony::ChronySocket::new(std::path::Path::new(path)) {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                log::error!("Failed to open the chrony socket: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let source: Box<dyn PositionSource> = match cli.source {
+        PositionSourceKind::Gpsd => Box::new(gps_interface::gpsd::GpsdSource::new(Some(&cli.gpsd_server), Some(cli.gpsd_port))),
+        PositionSourceKind::Serial => Box::new(gps_interface::serial::SerialSource::new(&cli.gps_usb_port, GPS_BAUDRATE, port_lock)),
+    };
+    let mut gps_interface = gps_interface::gps_interface::GPSInterface::new(source, socket_monitor.clone(),
+                                                                                   std::time::Duration::from_secs(cli.stale_timeout), chrony_socket);
 
-    tokio::spawn( async move {
+    //The GPS interface gets its own arbiter too, so the serial source can hand the receiver port over to ubxtool
+    //while the GPS control blocks waiting for it.
+    Arbiter::new().spawn(async move {
         gps_interface.run_handler().await;
     });
 
     //if cli.start {
-        match cli.mode {
-            Modes::RTKRover{username, password, server, mount_point, port} => {
-                gps_control.do_send(GPSMode::RtcmIn(username, password, server, mount_point, port));
-            },
-            Modes::RTKBase{username, password, server, mount_point, port, survey_dwell_time, survey_position_accuracy, fixed_ecef_x, fixed_ecef_y, fixed_ecef_z, fixed_ecef_accuracy} => {
-                gps_control.do_send(GPSMode::Base(username, password, server, mount_point, port, survey_dwell_time, survey_position_accuracy, fixed_ecef_x, fixed_ecef_y, fixed_ecef_z, fixed_ecef_accuracy));
-            },
-            Modes::PPPMode{data_directory, filename, interval, number_of_collections} => {
-                gps_control.do_send(GPSMode::RAW(data_directory, filename, interval, number_of_collections));
-            },
+        match mode {
             Modes::Standalone => {
                 //gps_control.do_send(GPSMode::Standalone); //Not necessary.
+            },
+            mode => {
+                gps_control.do_send(GPSMode::from(mode));
             }
         };
     //}
 
-    use actix_web::{middleware, web, App, HttpServer};
-    HttpServer::new(move || {
+    let (shutdown_sender, shutdown_receiver) = tokio::sync::mpsc::channel::<shutdown::ShutdownRequest>(1);
+    let shutdown_sender = actix_web::web::Data::new(shutdown_sender);
+    let shutdown_gps_control = gps_control.clone();
+
+    use actix_web::{dev::Service, middleware, web, App, HttpServer, ResponseError};
+    use futures::future::{ready, Either, FutureExt};
+    let server = HttpServer::new(move || {
+        let request_authentication = authentication.clone();
         App::new()
             .app_data(actix_web::web::Data::new((socket_monitor.clone(), gps_control.clone(), settings_handler.clone())))
+            .app_data(authentication.clone())
+            .app_data(shutdown_sender.clone())
+            .app_data(start_time.clone())
+            .app_data(actix_web::web::Data::new(supervisor.clone()))
+            // check the authentication before handling protected requests
+            .wrap_fn(move |req, srv| {
+                if request_authentication.is_allowed(&req) {
+                    Either::Left(srv.call(req).map(|res| res.map(|res| res.map_into_left_body())))
+                } else {
+                    log::warn!("Rejected unauthenticated request to {}.", req.path());
+                    Either::Right(ready(Ok(req.into_response(error::ApiError::Unauthorized.error_response()).map_into_right_body())))
+                }
+            })
             // enable logger
             .wrap(middleware::Logger::default())
             // serve static files
@@ -84,13 +148,45 @@ async fn main() -> std::io::Result<()> {
             .service(web::resource("/api/subscribe").route(web::get().to(web_socket::ws_index)))
             // rest API
             .service(web::scope("/api")
+                        .service(auth::login)
+                        .service(auth::logout)
                         .service(api::start)
+                        .service(api::set_mode)
+                        .service(api::get_profiles)
+                        .service(api::save_profile)
+                        .service(api::delete_profile)
+                        .service(api::activate_profile)
                         .service(api::get_settings)
                         .service(api::set_settings)
+                        .service(api::get_status)
+                        .service(api::get_sky)
+                        .service(api::get_devices)
+                        .service(api::get_time)
+                        .service(api::get_survey)
+                        .service(api::save_survey)
+                        .service(api::get_sites)
+                        .service(api::delete_site)
+                        .service(api::import_site)
+                        .service(api::transform_position)
+                        .service(api::get_processes)
+                        .service(api::get_process_output)
                         .service(api::shutdown))
             
     })
+    // SIGTERM and SIGINT are handled by the shutdown handler, so the child processes are stopped too.
+    .disable_signals()
     .bind(("0.0.0.0", cli.web_port))?
-    .run()
-    .await    
+    .run();
+
+    let shutdown = tokio::spawn(shutdown::shutdown_handler(shutdown_receiver, shutdown_gps_control, server.handle(), cli.power_off_on_shutdown));
+
+    //The server only stops through the shutdown handler, which still has to flush the log and power off the host
+    //before the runtime is dropped.
+    let result = server.await;
+    if result.is_ok() {
+        if let Err(e) = shutdown.await {
+            log::error!("The shutdown handler failed: {}", e);
+        }
+    }
+    result
 }
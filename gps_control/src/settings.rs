@@ -1,18 +1,45 @@
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
-use clap::{Parser, Subcommand};
+use clap::{ArgMatches, Parser, Subcommand, ValueEnum, ValueSource};
 use actix::prelude::*;
 use serde::{Serialize, Deserialize};
 
+use crate::geodesy::coordinates::{Ecef, Llh};
+use crate::gps_interface::gps_control::{FixedPosition, GPSControl, GPSMode, SettingsChanged};
+use crate::gps_interface::survey::SurveyStatus;
+
+/// Default location of the persistent settings file.
+pub const SETTINGS_FILE: &str = "settings.toml";
+
 
 /// This structure are the command line parameters passed to the system from the command line.
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 pub struct Cli {
-    /// System Mode
+    /// System Mode. The arguments given on the command line or through the environment replace those of the mode
+    /// stored in the settings file, a mode given without any of its own arguments doesn't replace a different stored mode.
     #[clap(subcommand)]
-    pub mode: Modes,
+    pub mode: Option<Modes>,
+
+    /// Settings file the system settings are loaded from and saved to.
+    #[clap(default_value = SETTINGS_FILE, long, env = "GPS_SETTINGS_FILE")]
+    pub settings_file: String,
+
+    /// Optional root only file the NTRIP password is loaded from and saved to, instead of the settings file.
+    #[clap(long, env = "GPS_SECRETS_FILE")]
+    pub secrets_file: Option<String>,
+
+    /// Named settings profile to start with, instead of the mode stored in the settings file.
+    #[clap(long, env = "GPS_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Where the receiver data is read from: gpsd, or the receiver on the GPS USB port directly.
+    #[clap(value_enum, default_value_t = PositionSourceKind::Gpsd, long, env = "GPS_SOURCE")]
+    pub source: PositionSourceKind,
 
     /// ip address of the GPSD server
     #[clap(default_value = "127.0.0.1", long)]
@@ -38,30 +65,51 @@ pub struct Cli {
     #[clap(default_value_t = 8080, long)]
     pub web_port: u16,
 
+    /// chrony SOCK refclock socket to send the PPS time samples to, for when gpsd isn't feeding chrony itself.
+    #[clap(long)]
+    pub chrony_socket: Option<String>,
+
+    /// Seconds without a position from the receiver before the GPS data is marked as stale.
+    #[clap(default_value_t = 5, long)]
+    pub stale_timeout: u64,
+
+    /// Power off the host when the system is shut down through the API (not on SIGTERM or SIGINT).
+    #[clap(long, action)]
+    pub power_off_on_shutdown: bool,
+
     /// Start data collection/corrections immediately.
     #[clap(default_value_t = false, long, action)]
     pub start: bool,
 }
 
+/// Position sources the receiver data can be read from.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PositionSourceKind {
+    /// The gpsd daemon, started by gps_control if it isn't already running.
+    Gpsd,
+    /// The receiver's serial port, read without gpsd. RAW (RINEX) collection needs gpsd.
+    Serial,
+}
+
 /// These are the settings associated with the various sub modes.
-#[derive(Subcommand, Clone, Serialize, Deserialize)]
+#[derive(Subcommand, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Modes {
     /// Set the system into RTK rover
     RTKRover {
         /// NTRIP server username
-        #[clap(default_value="", long)]
+        #[clap(default_value="", long, env = "NTRIP_USERNAME")]
         username: String,
 
         /// NTRIP server password
-        #[clap(long, default_value="")]
+        #[clap(long, default_value="", env = "NTRIP_PASSWORD")]
         password: String,
 
         /// NTRIP server address
-        #[clap(default_value = "rtk2go.com", long)]
+        #[clap(default_value = "rtk2go.com", long, env = "NTRIP_SERVER")]
         server: String,
 
         /// NTRIP mount point
-        #[clap(long, default_value = "")]
+        #[clap(long, default_value = "", env = "NTRIP_MOUNT_POINT")]
         mount_point: String,
 
         /// NTRIP server port
@@ -71,19 +119,19 @@ pub enum Modes {
     /// Set the systen into  RTK base mode
     RTKBase {
         /// NTRIP server username
-        #[clap(default_value="", long)]
+        #[clap(default_value="", long, env = "NTRIP_USERNAME")]
         username: String,
 
         /// NTRIP server password
-        #[clap(default_value = "", long)]
+        #[clap(default_value = "", long, env = "NTRIP_PASSWORD")]
         password: String,
 
         /// NTRIP server address
-        #[clap(default_value = "rtk2go.com", long)]
+        #[clap(default_value = "rtk2go.com", long, env = "NTRIP_SERVER")]
         server: String,
 
         /// NTRIP mount point
-        #[clap(default_value = "", long)]
+        #[clap(default_value = "", long, env = "NTRIP_MOUNT_POINT")]
         mount_point: String,
 
         /// NTRIP server port
@@ -99,20 +147,40 @@ pub enum Modes {
         survey_position_accuracy: u32,
 
         /// ecef X position in cm for fixed mode
-        #[clap(long)]
+        #[clap(long, allow_hyphen_values = true)]
         fixed_ecef_x: Option<f64>,
 
         /// ecef Y position in cm for fixed mode
-        #[clap(long)]
+        #[clap(long, allow_hyphen_values = true)]
         fixed_ecef_y: Option<f64>,
 
         /// ecef Z position in cm for fixed mode
-        #[clap(long)]
+        #[clap(long, allow_hyphen_values = true)]
         fixed_ecef_z: Option<f64>,
 
-        /// ecef accuracy in cm for fixed mode
-        #[clap(long)]
-        fixed_ecef_accuracy: Option<f64>,
+        /// Accuracy in cm of the fixed mode position, given either in ecef or as latitude, longitude and height
+        #[clap(long, alias = "fixed-ecef-accuracy")]
+        #[serde(alias = "fixed_ecef_accuracy")]
+        fixed_position_accuracy: Option<f64>,
+
+        /// WGS84 latitude in degrees for fixed mode, instead of the ecef position
+        #[clap(long, allow_hyphen_values = true)]
+        #[serde(default)]
+        fixed_latitude: Option<f64>,
+
+        /// WGS84 longitude in degrees for fixed mode
+        #[clap(long, allow_hyphen_values = true)]
+        #[serde(default)]
+        fixed_longitude: Option<f64>,
+
+        /// WGS84 ellipsoidal height in m for fixed mode
+        #[clap(long, allow_hyphen_values = true)]
+        #[serde(default)]
+        fixed_height: Option<f64>,
+
+        /// Saved site to use as the fixed position, instead of the fixed_ecef values.
+        #[clap(long, env = "GPS_SITE")]
+        site: Option<String>,
     },
     /// Set the system into post processing raw capture mode
     PPPMode {
@@ -137,10 +205,500 @@ pub enum Modes {
     Standalone,
 }
 
+/// Smallest and largest allowed survey in position accuracy, in 0.1 mm (1 mm to 100 m).
+const SURVEY_ACCURACY_RANGE: std::ops::RangeInclusive<u32> = 10..=1_000_000;
+
+impl Modes {
+    /// Check the mode settings, returning a validation error listing every invalid field.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let mut errors = Vec::new();
+        match self {
+            Modes::RTKRover{server, mount_point, port, ..} => {
+                validate_ntrip(server, mount_point, *port, &mut errors);
+            },
+            Modes::RTKBase{server, mount_point, port, survey_position_accuracy, fixed_ecef_x, fixed_ecef_y, fixed_ecef_z, fixed_position_accuracy,
+                           fixed_latitude, fixed_longitude, fixed_height, site, ..} => {
+                validate_ntrip(server, mount_point, *port, &mut errors);
+                if !SURVEY_ACCURACY_RANGE.contains(survey_position_accuracy) {
+                    errors.push(FieldError::new("survey_position_accuracy",
+                        &format!("must be between {} and {} (0.1 mm)", SURVEY_ACCURACY_RANGE.start(), SURVEY_ACCURACY_RANGE.end())));
+                }
+                let ecef = validate_position(&[("fixed_ecef_x", fixed_ecef_x), ("fixed_ecef_y", fixed_ecef_y), ("fixed_ecef_z", fixed_ecef_z)], &mut errors);
+                let llh = validate_position(&[("fixed_latitude", fixed_latitude), ("fixed_longitude", fixed_longitude), ("fixed_height", fixed_height)], &mut errors);
+                if ecef && llh {
+                    errors.push(FieldError::new("fixed_latitude", "cannot be used together with a fixed ecef position"));
+                }
+                if let Some(latitude) = fixed_latitude {
+                    if latitude.abs() > 90. {
+                        errors.push(FieldError::new("fixed_latitude", "must be between -90 and 90 degrees"));
+                    }
+                }
+                if let Some(longitude) = fixed_longitude {
+                    if longitude.abs() > 180. {
+                        errors.push(FieldError::new("fixed_longitude", "must be between -180 and 180 degrees"));
+                    }
+                }
+                if ecef || llh {
+                    match fixed_position_accuracy {
+                        None => errors.push(FieldError::new("fixed_position_accuracy", "must be set with a fixed position")),
+                        Some(accuracy) if !accuracy.is_finite() || *accuracy <= 0. => errors.push(FieldError::new("fixed_position_accuracy", "must be greater than 0")),
+                        Some(_) => (),
+                    }
+                    if site.is_some() {
+                        errors.push(FieldError::new("site", "cannot be used together with a fixed position"));
+                    }
+                }
+            },
+            Modes::PPPMode{data_directory, filename, interval, ..} => {
+                if let Err(message) = check_writable_directory(Path::new(data_directory)) {
+                    errors.push(FieldError::new("data_directory", &message));
+                }
+                if filename.is_empty() {
+                    errors.push(FieldError::new("filename", "must not be empty"));
+                }
+                if *interval == 0 {
+                    errors.push(FieldError::new("interval", "must be greater than 0"));
+                }
+            },
+            Modes::Standalone => (),
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(SettingsError::Validation(errors))
+        }
+    }
+}
+
+/// Validate one of the fixed positions, whose values must all be set together. Returns whether the position is set.
+fn validate_position(fields: &[(&str, &Option<f64>)], errors: &mut Vec<FieldError>) -> bool {
+    if fields.iter().all(|(_, value)| value.is_none()) {
+        return false;
+    }
+    for (field, value) in fields {
+        match value {
+            None => errors.push(FieldError::new(field, "all fixed position values must be set together")),
+            Some(value) if !value.is_finite() => errors.push(FieldError::new(field, "must be a finite number")),
+            Some(_) => (),
+        }
+    }
+    true
+}
+
+/// Validate the NTRIP connection settings shared by the base and rover modes.
+fn validate_ntrip(server: &str, mount_point: &str, port: u16, errors: &mut Vec<FieldError>) {
+    if port == 0 {
+        errors.push(FieldError::new("port", "must be between 1 and 65535"));
+    }
+    if !server.is_empty() && mount_point.is_empty() {
+        errors.push(FieldError::new("mount_point", "must not be empty when a server is set"));
+    }
+}
+
+/// Check that the directory exists and that files can be created in it.
+fn check_writable_directory(directory: &Path) -> Result<(), String> {
+    if !directory.is_dir() {
+        return Err("directory does not exist".to_string());
+    }
+    let probe = directory.join(".gps_control_write_test");
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(())
+        },
+        Err(e) => Err(format!("directory is not writable: {}", e)),
+    }
+}
+
+/// Placeholder returned in place of passwords. Sending it back leaves the stored password unchanged.
+pub const REDACTED_PASSWORD: &str = "********";
+
+impl Modes {
+    /// Name of the mode, as used in the settings file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Modes::RTKRover{..} => "RTKRover",
+            Modes::RTKBase{..} => "RTKBase",
+            Modes::PPPMode{..} => "PPPMode",
+            Modes::Standalone => "Standalone",
+        }
+    }
+
+    /// Fixed position of a base station, from either the ecef position in cm or the latitude, longitude and height.
+    /// None when the base surveys in its position.
+    pub fn fixed_position(&self) -> Option<FixedPosition> {
+        match self {
+            Modes::RTKBase{fixed_ecef_x: Some(x), fixed_ecef_y: Some(y), fixed_ecef_z: Some(z), fixed_position_accuracy: Some(accuracy), ..} => {
+                Some(FixedPosition::Ecef(Ecef { x: x / 100., y: y / 100., z: z / 100. }, accuracy / 100.))
+            },
+            Modes::RTKBase{fixed_latitude: Some(latitude), fixed_longitude: Some(longitude), fixed_height: Some(height), fixed_position_accuracy: Some(accuracy), ..} => {
+                Some(FixedPosition::Llh(Llh { latitude: *latitude, longitude: *longitude, height: *height }, accuracy / 100.))
+            },
+            _ => None,
+        }
+    }
+
+    /// NTRIP password of the mode, if it has one.
+    pub fn password(&self) -> Option<&str> {
+        match self {
+            Modes::RTKRover{password, ..} | Modes::RTKBase{password, ..} => Some(password),
+            _ => None,
+        }
+    }
+
+    fn password_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Modes::RTKRover{password, ..} | Modes::RTKBase{password, ..} => Some(password),
+            _ => None,
+        }
+    }
+
+    /// Copy of the settings that is safe to return through the API, with any set password replaced by REDACTED_PASSWORD.
+    pub fn redacted(&self) -> Modes {
+        let mut mode = self.clone();
+        if let Some(password) = mode.password_mut() {
+            if !password.is_empty() {
+                *password = REDACTED_PASSWORD.to_string();
+            }
+        }
+        mode
+    }
+
+    /// Passwords are write only, replace a REDACTED_PASSWORD placeholder with the currently stored password.
+    fn keep_password(&mut self, current: &Modes) {
+        let current_password = current.password().unwrap_or("").to_string();
+        if let Some(password) = self.password_mut() {
+            if password == REDACTED_PASSWORD {
+                *password = current_password;
+            }
+        }
+    }
+}
+
+/// Credentials kept in a separate file that should only be readable by root.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Secrets {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ntrip_password: Option<String>,
+
+    /// NTRIP passwords of the stored profiles, by profile name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profile_passwords: BTreeMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_password: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+}
+
+impl Secrets {
+    /// Load the secrets file, warning if other users are able to read it.
+    pub fn load(path: &Path) -> Result<Self, SettingsError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let permissions = std::fs::metadata(path)?.permissions();
+        if permissions.mode() & 0o077 != 0 {
+            log::warn!("The secrets file {} can be accessed by other users, it should only be readable by its owner (chmod 600).", path.display());
+        }
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str::<Secrets>(&contents).map_err(|e| SettingsError::Storage(e.to_string()))
+    }
+}
+
+/// Web API authentication settings. The API is only protected when a password or token is set.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuthSettings {
+    /// Password used to log in and get a session cookie.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+
+    /// Token accepted as a bearer token in the Authorization header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// Allow the read only API (settings, status and the data web socket) without logging in.
+    #[serde(default = "default_public_status")]
+    pub public_status: bool,
+}
+
+fn default_public_status() -> bool {
+    true
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        AuthSettings { password: None, token: None, public_status: default_public_status() }
+    }
+}
+
+/// The contents of the persistent settings file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredSettings {
+    pub mode: Modes,
+
+    /// Named settings that can be activated to switch the system mode, by profile name.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Modes>,
+
+    #[serde(default)]
+    pub auth: AuthSettings,
+
+    /// Surveyed base station positions, by site name.
+    #[serde(default)]
+    pub sites: BTreeMap<String, SavedSite>,
+}
+
+/// Base station position saved from a finished survey-in, so that later base starts can use it as their fixed position.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedSite {
+    /// ECEF position in m.
+    pub ecef_x: f64,
+    pub ecef_y: f64,
+    pub ecef_z: f64,
+    /// 3D accuracy of the position in m.
+    pub accuracy: f64,
+    /// When the position was saved, in seconds since the unix epoch.
+    pub saved_at: u64,
+    /// Length of the survey in seconds, and the number of positions averaged. 0 for imported positions.
+    pub duration_seconds: u32,
+    pub observations: u32,
+    #[serde(default)]
+    pub source: SiteSource,
+}
+
+/// Where a saved site position came from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SiteSource {
+    /// The receiver's survey-in.
+    #[default]
+    Survey,
+    /// An NRCan CSRS-PPP solution, transformed to ITRF2020.
+    CsrsPpp,
+}
+
+/// Seconds since the unix epoch.
+fn unix_time() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl SavedSite {
+    /// Site from the mean position of a survey-in, saved now.
+    pub fn from_survey(survey: &SurveyStatus) -> Self {
+        SavedSite {
+            ecef_x: survey.mean_ecef_x,
+            ecef_y: survey.mean_ecef_y,
+            ecef_z: survey.mean_ecef_z,
+            accuracy: survey.accuracy,
+            saved_at: unix_time(),
+            duration_seconds: survey.duration_seconds,
+            observations: survey.observations,
+            source: SiteSource::Survey,
+        }
+    }
+
+    /// Site from an imported CSRS-PPP position, in m, saved now.
+    pub fn from_ppp(position: &Ecef, accuracy: f64) -> Self {
+        SavedSite {
+            ecef_x: position.x,
+            ecef_y: position.y,
+            ecef_z: position.z,
+            accuracy: accuracy,
+            saved_at: unix_time(),
+            duration_seconds: 0,
+            observations: 0,
+            source: SiteSource::CsrsPpp,
+        }
+    }
+}
+
+impl StoredSettings {
+    /// Load the settings file, if it exists, and layer the mode given on the command line (or
+    /// through environment variables) on top of it. Only the arguments that were explicitly given
+    /// replace the stored values, see layer_mode.
+    ///  - path: settings file location.
+    ///  - secrets_path: optional secrets file, its password replaces the one in the settings file.
+    ///  - profile: optional stored profile to start with instead of the stored mode.
+    ///  - cli_mode: mode given on the command line, if any.
+    ///  - cli_matches: argument matches of the mode subcommand, used to find explicit arguments.
+    pub fn load(path: &Path, secrets_path: Option<&Path>, profile: Option<&str>,
+                cli_mode: Option<Modes>, cli_matches: Option<&ArgMatches>) -> Result<Self, SettingsError> {
+        let stored = if path.exists() {
+            log::info!("Loading settings from {}.", path.display());
+            let contents = std::fs::read_to_string(path)?;
+            Some(toml::from_str::<StoredSettings>(&contents).map_err(|e| SettingsError::Storage(e.to_string()))?)
+        } else {
+            log::info!("No settings file found at {}, using the command line settings.", path.display());
+            None
+        };
+
+        let secrets = match secrets_path {
+            Some(secrets_path) if secrets_path.exists() => {
+                log::info!("Loading secrets from {}.", secrets_path.display());
+                Secrets::load(secrets_path)?
+            },
+            _ => Secrets::default(),
+        };
+
+        let mut settings = stored.unwrap_or(StoredSettings {
+            mode: cli_mode.clone().unwrap_or(Modes::Standalone),
+            profiles: BTreeMap::new(),
+            auth: AuthSettings::default(),
+            sites: BTreeMap::new(),
+        });
+        settings.apply_secrets(&secrets);
+        if let Some(profile) = profile {
+            match settings.profiles.get(profile) {
+                Some(mode) => {
+                    log::info!("Starting with the {} profile.", profile);
+                    settings.mode = mode.clone();
+                },
+                None => log::error!("No profile named {}, starting with the stored mode.", profile),
+            }
+        }
+        if let (Some(cli_mode), Some(cli_matches)) = (cli_mode, cli_matches) {
+            settings.mode = layer_mode(settings.mode, cli_mode, cli_matches);
+        }
+        Ok(settings)
+    }
+
+    /// Copy of the mode with the position of its saved site, if it uses one, filled into the fixed_ecef
+    /// values (in cm) the receiver is configured with.
+    pub fn resolve_site(&self, mode: &Modes) -> Result<Modes, SettingsError> {
+        let mut mode = mode.clone();
+        if let Modes::RTKBase{site: Some(ref name), ref mut fixed_ecef_x, ref mut fixed_ecef_y, ref mut fixed_ecef_z, ref mut fixed_position_accuracy, ..} = mode {
+            let site = self.sites.get(name).ok_or_else(|| SettingsError::SiteNotFound(name.clone()))?;
+            *fixed_ecef_x = Some(site.ecef_x * 100.);
+            *fixed_ecef_y = Some(site.ecef_y * 100.);
+            *fixed_ecef_z = Some(site.ecef_z * 100.);
+            *fixed_position_accuracy = Some(site.accuracy * 100.);
+        }
+        Ok(mode)
+    }
+
+    fn apply_secrets(&mut self, secrets: &Secrets) {
+        if let (Some(password), Some(secret)) = (self.mode.password_mut(), &secrets.ntrip_password) {
+            *password = secret.clone();
+        }
+        for (name, profile) in self.profiles.iter_mut() {
+            if let (Some(password), Some(secret)) = (profile.password_mut(), secrets.profile_passwords.get(name)) {
+                *password = secret.clone();
+            }
+        }
+        if secrets.auth_password.is_some() {
+            self.auth.password = secrets.auth_password.clone();
+        }
+        if secrets.auth_token.is_some() {
+            self.auth.token = secrets.auth_token.clone();
+        }
+    }
+
+    /// Atomically save the settings by writing to a temporary file and renaming it over the old one.
+    /// When a secrets file is used, the password is saved there instead of in the settings file.
+    pub fn save(&self, path: &Path, secrets_path: Option<&Path>) -> Result<(), SettingsError> {
+        let mut settings = self.clone();
+        if let Some(secrets_path) = secrets_path {
+            let secrets = Secrets {
+                ntrip_password: settings.mode.password_mut().map(std::mem::take),
+                profile_passwords: settings.profiles.iter_mut()
+                    .filter_map(|(name, profile)| profile.password_mut().map(|password| (name.clone(), std::mem::take(password))))
+                    .collect(),
+                auth_password: settings.auth.password.take(),
+                auth_token: settings.auth.token.take(),
+            };
+            let contents = toml::to_string(&secrets).map_err(|e| SettingsError::Storage(e.to_string()))?;
+            write_atomically(secrets_path, &contents, 0o600)?;
+        }
+
+        let contents = toml::to_string(&settings).map_err(|e| SettingsError::Storage(e.to_string()))?;
+        write_atomically(path, &contents, 0o644)?;
+        Ok(())
+    }
+}
+
+/// Write the file contents to a temporary file with the given permissions and rename it over the old file.
+fn write_atomically(path: &Path, contents: &str, mode: u32) -> Result<(), SettingsError> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(mode).open(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Replace the values of the stored mode with those explicitly set on the command line or in the
+/// environment, ignoring the empty environment variables of the .service files. A command line mode
+/// that differs from the stored one replaces it only when some of its arguments were given, so the
+/// startup scripts' mode doesn't undo a mode set through the API.
+fn layer_mode(stored: Modes, cli_mode: Modes, cli_matches: &ArgMatches) -> Modes {
+    let (mut stored_value, cli_value) = match (serde_json::to_value(&stored), serde_json::to_value(&cli_mode)) {
+        (Ok(stored_value), Ok(cli_value)) => (stored_value, cli_value),
+        _ => return cli_mode,
+    };
+
+    //Modes are externally tagged, so the fields are in the only entry of the outer object. clap names the
+    //arguments after the fields in kebab case.
+    let cli_fields = cli_value.as_object().and_then(|variant| variant.values().next()).and_then(|fields| fields.as_object());
+    let explicit: Vec<(&String, &serde_json::Value)> = cli_fields.into_iter().flatten().filter(|(name, value)| {
+        match cli_matches.value_source(name.replace('_', "-").as_str()) {
+            Some(ValueSource::CommandLine) => true,
+            Some(ValueSource::EnvVariable) => value.as_str() != Some(""),
+            _ => false,
+        }
+    }).collect();
+
+    if std::mem::discriminant(&stored) != std::mem::discriminant(&cli_mode) {
+        if explicit.is_empty() {
+            log::info!("Starting in the stored mode, the {} mode was given without any of its settings.", cli_mode.name());
+            return stored;
+        }
+        return cli_mode;
+    }
+
+    if let Some(stored_fields) = stored_value.as_object_mut().and_then(|variant| variant.values_mut().next()).and_then(|fields| fields.as_object_mut()) {
+        for (name, value) in explicit {
+            stored_fields.insert(name.clone(), value.clone());
+        }
+    }
+    serde_json::from_value(stored_value).unwrap_or(cli_mode)
+}
+
+/// A single invalid setting and the reason it was rejected.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &str, message: &str) -> Self {
+        FieldError { field: field.to_string(), message: message.to_string() }
+    }
+}
+
 /// Error types that can occur when setting the settings.
 #[derive(Debug)]
 pub enum SettingsError {
     InvalidMode,
+    Storage(String),
+    Validation(Vec<FieldError>),
+    ProfileNotFound(String),
+    SiteNotFound(String),
+    /// The GPS control failed to apply the new settings, so they weren't saved.
+    Apply(String),
+}
+
+impl From<std::io::Error> for SettingsError {
+    fn from(e: std::io::Error) -> Self {
+        SettingsError::Storage(e.to_string())
+    }
 }
 
 impl Error for SettingsError{
@@ -151,28 +709,136 @@ impl std::fmt::Display for SettingsError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             SettingsError::InvalidMode => write! (f, "Mismatched Settings Modes."),
+            SettingsError::Storage(e) => write! (f, "Failed to load or save the settings file: {}", e),
+            SettingsError::Validation(errors) => {
+                let fields: Vec<String> = errors.iter().map(|e| format!("{} {}", e.field, e.message)).collect();
+                write! (f, "Invalid settings: {}", fields.join(", "))
+            },
+            SettingsError::ProfileNotFound(name) => write! (f, "No profile named {}.", name),
+            SettingsError::SiteNotFound(name) => write! (f, "No saved site named {}.", name),
+            SettingsError::Apply(e) => write! (f, "GPS control failed to apply the settings: {}", e),
         }
     }
 }
 
 
-///This is the settings actor
+///This is the settings actor. SetMode and ActivateProfile switch the GPS control to the new mode, which is only
+/// stored once it has been applied. Each returns the stored mode.
 #[derive(Message)]
 #[rtype(result = "Result<Modes, SettingsError>")]
 pub enum SettingsMessage {
     SetSettings(Modes),
+    SetMode(Modes),
+    ActivateProfile(String),
     GetSettings()
 }
 
+/// Message for managing the stored profiles, returns all the stored profiles.
+#[derive(Message)]
+#[rtype(result = "Result<BTreeMap<String, Modes>, SettingsError>")]
+pub enum ProfileMessage {
+    GetProfiles(),
+    SaveProfile(String, Modes),
+    DeleteProfile(String),
+}
+
+/// Message for managing the saved base station sites, returns all the saved sites.
+#[derive(Message)]
+#[rtype(result = "Result<BTreeMap<String, SavedSite>, SettingsError>")]
+pub enum SiteMessage {
+    GetSites(),
+    SaveSite(String, SavedSite),
+    DeleteSite(String),
+}
+
+/// Asks the settings actor for the summary of the settings reported by /api/status.
+#[derive(Message)]
+#[rtype(result = "SettingsStatus")]
+pub struct GetSettingsStatus;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SettingsStatus {
+    pub mode: &'static str,
+    /// Stored profile matching the current settings, if any.
+    pub profile: Option<String>,
+    pub valid: bool,
+}
+
+/// Profile and site names are used in URLs, so they are limited to letters, numbers, '-' and '_'.
+fn validate_profile_name(name: &str) -> Result<(), SettingsError> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(SettingsError::Validation(vec![FieldError::new("name", "must be made of letters, numbers, '-' and '_'")]));
+    }
+    Ok(())
+}
+
 /// This structure is an actor for handling the system settings. Individual settings
-/// can be changes, but the overall mode cannot.
+/// can be changed with SetSettings, switching the overall mode is done with SetMode.
+/// Changes to the active mode are passed on to the GPS control, and only saved to the settings file once it has applied them.
 pub struct SettingsHandler {
-    settings: Modes,
+    settings: StoredSettings,
+    path: PathBuf,
+    secrets_path: Option<PathBuf>,
+    gps_control: Addr<GPSControl>,
 }
 
 impl SettingsHandler {
-    pub fn new(settings: Modes) -> Self {
-        SettingsHandler{ settings: settings}
+    pub fn new(settings: StoredSettings, path: PathBuf, secrets_path: Option<PathBuf>, gps_control: Addr<GPSControl>) -> Self {
+        SettingsHandler{ settings: settings, path: path, secrets_path: secrets_path, gps_control: gps_control }
+    }
+
+    fn save(&self) -> Result<(), SettingsError> {
+        self.settings.save(&self.path, self.secrets_path.as_deref()).map_err(|e| {
+            log::error!("Failed to save the settings to {}: {}", self.path.display(), e);
+            e
+        })
+    }
+
+    /// Wait for the GPS control to apply a change, then store and save the new mode. A change that fails to
+    /// apply leaves the stored settings as they were.
+    fn apply_then_save<F>(&self, mode: Modes, applied: F) -> AtomicResponse<Self, Result<Modes, SettingsError>>
+        where F: std::future::Future<Output = Result<Result<(), Box<dyn Error + Send + Sync>>, MailboxError>> + 'static {
+        AtomicResponse::new(Box::pin(applied.into_actor(self).map(move |result, actor, _ctx| {
+            match result {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => return Err(SettingsError::Apply(e.to_string())),
+                Err(e) => return Err(SettingsError::Apply(e.to_string())),
+            }
+            actor.settings.mode = mode;
+            actor.save()?;
+            Ok(actor.settings.mode.clone())
+        })))
+    }
+
+    /// Change the settings of the current mode. Changes the GPS control has to act on are passed on to it, and
+    /// only stored once it has applied them.
+    fn set_settings(&mut self, mut settings: Modes) -> AtomicResponse<Self, Result<Modes, SettingsError>> {
+        settings.keep_password(&self.settings.mode);
+        if std::mem::discriminant(&self.settings.mode) != std::mem::discriminant (&settings) {
+            log::error!("Settings Modes did not match, the provided settings are for an incompatible mode.");
+            return ready(Err(SettingsError::InvalidMode));
+        }
+        let new_settings = match settings.validate().and_then(|_| self.settings.resolve_site(&settings)) {
+            Ok(new_settings) => new_settings,
+            Err(e) => return ready(Err(e)),
+        };
+        //The GPS control is sent the site positions, so a changed site is re-applied like a changed fixed position.
+        let old_settings = self.settings.resolve_site(&self.settings.mode).unwrap_or_else(|_| self.settings.mode.clone());
+
+        if old_settings == new_settings {
+            self.settings.mode = settings;
+            return ready(self.save().map(|_| self.settings.mode.clone()));
+        }
+        self.apply_then_save(settings, self.gps_control.send(SettingsChanged { old: old_settings, new: new_settings }))
+    }
+
+    /// Switch the GPS control to a new mode, with the position of its saved site filled in. The mode is only
+    /// stored once it has been applied.
+    fn switch_mode(&self, settings: Modes) -> AtomicResponse<Self, Result<Modes, SettingsError>> {
+        match settings.validate().and_then(|_| self.settings.resolve_site(&settings)) {
+            Ok(resolved) => self.apply_then_save(settings, self.gps_control.send(GPSMode::from(resolved))),
+            Err(e) => ready(Err(e)),
+        }
     }
 }
 
@@ -189,58 +855,290 @@ impl Actor for SettingsHandler {
 }
 
 impl Handler<SettingsMessage> for SettingsHandler {
-    type Result = Result<Modes, SettingsError>;
+    type Result = AtomicResponse<Self, Result<Modes, SettingsError>>;
 
     fn handle(&mut self, msg: SettingsMessage, _ctx: &mut Context<Self>) -> Self::Result {
         match msg {
-            SettingsMessage::SetSettings(settings) => {
-                if std::mem::discriminant(&self.settings) != std::mem::discriminant (&settings) {
-                    log::error!("Settings Modes did not match, the provided settings are for an incompatible mode.");
-                    return Err(SettingsError::InvalidMode)
+            SettingsMessage::SetSettings(settings) => self.set_settings(settings),
+            SettingsMessage::SetMode(mut settings) => {
+                settings.keep_password(&self.settings.mode);
+                log::info!("Switching the system mode.");
+                self.switch_mode(settings)
+            },
+            SettingsMessage::ActivateProfile(name) => {
+                log::info!("Activating the {} profile.", name);
+                match self.settings.profiles.get(&name).cloned() {
+                    Some(settings) => self.switch_mode(settings),
+                    None => ready(Err(SettingsError::ProfileNotFound(name))),
                 }
+            },
+            SettingsMessage::GetSettings() => ready(Ok(self.settings.mode.clone())),
+        }
+    }
+}
 
-                match settings {
-                    Modes::PPPMode{data_directory, filename, interval, number_of_collections} => {
-                        //todo validate the inputs.
-                        self.settings = Modes::PPPMode { data_directory: data_directory,
-                                                         filename: filename,
-                                                         interval: interval,
-                                                         number_of_collections: number_of_collections };
-                    },
-                    Modes::RTKBase{username, password, server, mount_point, port, survey_dwell_time, survey_position_accuracy, fixed_ecef_x, fixed_ecef_y, fixed_ecef_z, fixed_ecef_accuracy} => {
-                        //todo validate the inputs
-                        self.settings = Modes::RTKBase {
-                            username: username,
-                            password: password,
-                            server: server,
-                            mount_point: mount_point,
-                            port: port,
-                            survey_dwell_time: survey_dwell_time,
-                            survey_position_accuracy: survey_position_accuracy,
-                            fixed_ecef_x: fixed_ecef_x,
-                            fixed_ecef_y: fixed_ecef_y,
-                            fixed_ecef_z: fixed_ecef_z,
-                            fixed_ecef_accuracy: fixed_ecef_accuracy,
-                        };
-                    },
-                    Modes::RTKRover{username, password, server, mount_point, port} => {
-                        //todo validate the inputs
-                        self.settings = Modes::RTKRover {
-                            username: username,
-                            password: password,
-                            server: server,
-                            mount_point: mount_point,
-                            port: port
-                        };
-                    }
-                    Modes::Standalone => ()
+/// Reply of the settings actor that is already known.
+fn ready(result: Result<Modes, SettingsError>) -> AtomicResponse<SettingsHandler, Result<Modes, SettingsError>> {
+    AtomicResponse::new(Box::pin(fut::ready(result)))
+}
+
+impl Handler<GetSettingsStatus> for SettingsHandler {
+    type Result = MessageResult<GetSettingsStatus>;
+
+    fn handle(&mut self, _msg: GetSettingsStatus, _ctx: &mut Context<Self>) -> Self::Result {
+        let mode = &self.settings.mode;
+        MessageResult(SettingsStatus {
+            mode: mode.name(),
+            profile: self.settings.profiles.iter().find(|(_, profile)| *profile == mode).map(|(name, _)| name.clone()),
+            valid: mode.validate().is_ok(),
+        })
+    }
+}
+
+impl Handler<ProfileMessage> for SettingsHandler {
+    type Result = Result<BTreeMap<String, Modes>, SettingsError>;
+
+    fn handle(&mut self, msg: ProfileMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        match msg {
+            ProfileMessage::GetProfiles() => (),
+            ProfileMessage::SaveProfile(name, mut settings) => {
+                validate_profile_name(&name)?;
+                if let Some(current) = self.settings.profiles.get(&name) {
+                    settings.keep_password(current);
                 }
-                Ok(self.settings.clone())
+                settings.validate()?;
+                self.settings.resolve_site(&settings)?;
+                log::info!("Saving the {} profile.", name);
+                self.settings.profiles.insert(name, settings);
+                self.save()?;
             },
-            SettingsMessage::GetSettings() => {
-                Ok(self.settings.clone())
+            ProfileMessage::DeleteProfile(name) => {
+                if self.settings.profiles.remove(&name).is_none() {
+                    return Err(SettingsError::ProfileNotFound(name));
+                }
+                log::info!("Deleted the {} profile.", name);
+                self.save()?;
             }
         }
+        Ok(self.settings.profiles.clone())
     }
 }
 
+impl Handler<SiteMessage> for SettingsHandler {
+    type Result = Result<BTreeMap<String, SavedSite>, SettingsError>;
+
+    fn handle(&mut self, msg: SiteMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        match msg {
+            SiteMessage::GetSites() => (),
+            SiteMessage::SaveSite(name, site) => {
+                validate_profile_name(&name)?;
+                log::info!("Saving the base station position as the {} site: {:.4}, {:.4}, {:.4} m ±{:.4} m.",
+                           name, site.ecef_x, site.ecef_y, site.ecef_z, site.accuracy);
+                self.settings.sites.insert(name, site);
+                self.save()?;
+            },
+            SiteMessage::DeleteSite(name) => {
+                let in_use = std::iter::once(&self.settings.mode).chain(self.settings.profiles.values())
+                    .any(|mode| matches!(mode, Modes::RTKBase{site: Some(site), ..} if *site == name));
+                if in_use {
+                    return Err(SettingsError::Validation(vec![FieldError::new("site", "is used by the current settings or a profile")]));
+                }
+                if self.settings.sites.remove(&name).is_none() {
+                    return Err(SettingsError::SiteNotFound(name));
+                }
+                log::info!("Deleted the {} site.", name);
+                self.save()?;
+            }
+        }
+        Ok(self.settings.sites.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn base_mode() -> Modes {
+        Modes::RTKBase {
+            username: "".to_string(),
+            password: "".to_string(),
+            server: "rtk2go.com".to_string(),
+            mount_point: "BASE".to_string(),
+            port: 2101,
+            survey_dwell_time: 7200,
+            survey_position_accuracy: 15000,
+            fixed_ecef_x: None,
+            fixed_ecef_y: None,
+            fixed_ecef_z: None,
+            fixed_position_accuracy: None,
+            fixed_latitude: None,
+            fixed_longitude: None,
+            fixed_height: None,
+            site: None,
+        }
+    }
+
+    fn invalid_fields(mode: &Modes) -> Vec<String> {
+        match mode.validate() {
+            Err(SettingsError::Validation(errors)) => errors.into_iter().map(|e| e.field).collect(),
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn test_load_keeps_stored_mode () {
+        let path = std::env::temp_dir().join(format!("gps_control_settings_{}.toml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let settings = StoredSettings::load(&path, None, None, Some(Modes::Standalone), None).unwrap();
+        assert! (settings.mode == Modes::Standalone);
+
+        //Once a mode is stored, the startup scripts' mode without any settings doesn't replace it.
+        StoredSettings { mode: base_mode(), ..settings }.save(&path, None).unwrap();
+        let (cli_mode, cli_matches) = parse_mode(&["gps_control", "standalone"]);
+        let settings = StoredSettings::load(&path, None, None, Some(cli_mode), Some(&cli_matches)).unwrap();
+        assert! (settings.mode == base_mode());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Mode and subcommand matches of a command line.
+    fn parse_mode(args: &[&str]) -> (Modes, ArgMatches) {
+        let matches = Cli::command().try_get_matches_from(args).unwrap();
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        (cli.mode.unwrap(), matches.subcommand().unwrap().1.clone())
+    }
+
+    #[test]
+    fn test_negative_ecef_arguments () {
+        //The arguments rtk_base.sh passes for a site in the Americas, where Y is negative.
+        let (mode, _) = parse_mode(&["gps_control", "--start", "rtk-base", "--fixed-ecef-x", "-91850212.35", "--fixed-ecef-y", "-434627133.12",
+                                     "--fixed-ecef-z", "456197781.5", "--fixed-position-accuracy", "2"]);
+        match mode {
+            Modes::RTKBase { fixed_ecef_x, fixed_ecef_y, fixed_ecef_z, .. } => {
+                assert_eq! ((fixed_ecef_x, fixed_ecef_y, fixed_ecef_z), (Some(-91850212.35), Some(-434627133.12), Some(456197781.5)));
+            },
+            _ => panic!("expected the base mode"),
+        }
+    }
+
+    #[test]
+    fn test_layer_mode () {
+        //Only the explicit arguments replace the stored ones, not the clap defaults.
+        let (cli_mode, cli_matches) = parse_mode(&["gps_control", "rtk-base", "--mount-point", "NEW"]);
+        match layer_mode(base_mode(), cli_mode, &cli_matches) {
+            Modes::RTKBase { mount_point, server, survey_dwell_time, .. } => {
+                assert_eq! (mount_point, "NEW");
+                assert_eq! ((server.as_str(), survey_dwell_time), ("rtk2go.com", 7200));
+            },
+            _ => panic!("expected the base mode"),
+        }
+
+        //A different mode replaces the stored one when some of its settings are given.
+        let (cli_mode, cli_matches) = parse_mode(&["gps_control", "rtk-rover", "--mount-point", "ROVER"]);
+        assert! (matches!(layer_mode(base_mode(), cli_mode, &cli_matches), Modes::RTKRover { .. }));
+        let (cli_mode, cli_matches) = parse_mode(&["gps_control", "rtk-rover"]);
+        assert! (layer_mode(base_mode(), cli_mode, &cli_matches) == base_mode());
+    }
+
+    #[test]
+    fn test_valid_base () {
+        assert! (base_mode().validate().is_ok());
+        assert! (Modes::Standalone.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_ntrip () {
+        let mode = Modes::RTKRover {
+            username: "".to_string(),
+            password: "".to_string(),
+            server: "rtk2go.com".to_string(),
+            mount_point: "".to_string(),
+            port: 0,
+        };
+        assert_eq! (invalid_fields(&mode), vec!["port", "mount_point"]);
+    }
+
+    #[test]
+    fn test_partial_fixed_position () {
+        let mut mode = base_mode();
+        if let Modes::RTKBase{ref mut fixed_ecef_x, ref mut survey_position_accuracy, ..} = mode {
+            *fixed_ecef_x = Some(1.0);
+            *survey_position_accuracy = 0;
+        }
+        assert_eq! (invalid_fields(&mode), vec!["survey_position_accuracy", "fixed_ecef_y", "fixed_ecef_z", "fixed_position_accuracy"]);
+    }
+
+    #[test]
+    fn test_old_accuracy_name () {
+        let mut mode = base_mode();
+        if let Modes::RTKBase{ref mut fixed_position_accuracy, ..} = mode {
+            *fixed_position_accuracy = Some(2.0);
+        }
+        let stored = serde_json::to_string(&mode).unwrap().replace("fixed_position_accuracy", "fixed_ecef_accuracy");
+        assert! (serde_json::from_str::<Modes>(&stored).unwrap() == mode);
+    }
+
+    #[test]
+    fn test_fixed_llh_position () {
+        let mut mode = base_mode();
+        if let Modes::RTKBase{ref mut fixed_latitude, ref mut fixed_longitude, ref mut fixed_height, ref mut fixed_position_accuracy, ..} = mode {
+            *fixed_latitude = Some(45.0);
+            *fixed_longitude = Some(-75.0);
+            *fixed_height = Some(100.0);
+            *fixed_position_accuracy = Some(2.0);
+        }
+        assert! (mode.validate().is_ok());
+        assert_eq! (mode.fixed_position(), Some(FixedPosition::Llh(Llh { latitude: 45.0, longitude: -75.0, height: 100.0 }, 0.02)));
+
+        if let Modes::RTKBase{ref mut fixed_ecef_x, ref mut fixed_ecef_y, ref mut fixed_ecef_z, ref mut fixed_latitude, ..} = mode {
+            *fixed_ecef_x = Some(1.0);
+            *fixed_ecef_y = Some(1.0);
+            *fixed_ecef_z = Some(1.0);
+            *fixed_latitude = Some(91.0);
+        }
+        assert_eq! (invalid_fields(&mode), vec!["fixed_latitude", "fixed_latitude"]);
+    }
+
+    #[test]
+    fn test_resolve_site () {
+        let mut settings = StoredSettings { mode: Modes::Standalone, profiles: BTreeMap::new(), auth: AuthSettings::default(), sites: BTreeMap::new() };
+        settings.sites.insert("roof".to_string(), SavedSite {
+            ecef_x: 918_502.1235, ecef_y: -4_346_271.3312, ecef_z: 4_561_131.06, accuracy: 0.0145,
+            saved_at: 0, duration_seconds: 7200, observations: 7200, source: SiteSource::Survey,
+        });
+        let mut mode = base_mode();
+        if let Modes::RTKBase{ref mut site, ..} = mode {
+            *site = Some("roof".to_string());
+        }
+        match settings.resolve_site(&mode).unwrap() {
+            Modes::RTKBase{fixed_ecef_x, fixed_position_accuracy, ..} => {
+                assert! ((fixed_ecef_x.unwrap() - 91_850_212.35).abs() < 1e-6);
+                assert! ((fixed_position_accuracy.unwrap() - 1.45).abs() < 1e-9);
+            },
+            _ => panic!("resolved to a different mode"),
+        }
+
+        settings.sites.clear();
+        assert! (matches!(settings.resolve_site(&mode), Err(SettingsError::SiteNotFound(_))));
+    }
+
+    #[test]
+    fn test_ppp_mode () {
+        let mode = Modes::PPPMode {
+            data_directory: std::env::temp_dir().to_string_lossy().to_string(),
+            filename: "raw_data.obs".to_string(),
+            interval: 30,
+            number_of_collections: 2880,
+        };
+        assert! (mode.validate().is_ok());
+
+        let mode = Modes::PPPMode {
+            data_directory: "/this/directory/does/not/exist".to_string(),
+            filename: "raw_data.obs".to_string(),
+            interval: 0,
+            number_of_collections: 2880,
+        };
+        assert_eq! (invalid_fields(&mode), vec!["data_directory", "interval"]);
+    }
+}
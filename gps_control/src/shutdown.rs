@@ -0,0 +1,81 @@
+use actix::prelude::*;
+use actix_web::dev::ServerHandle;
+use std::process::Command;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+
+use crate::gps_interface::gps_control::{GPSControl, GPSMode};
+
+/// Request to shut the system down, sent from the API.
+#[derive(Debug)]
+pub struct ShutdownRequest {
+    /// Power off the host once the software has stopped.
+    pub power_off: bool,
+}
+
+pub type ShutdownSender = mpsc::Sender<ShutdownRequest>;
+
+/// Wait for a shutdown request from the API, SIGTERM (from systemd) or SIGINT, then shut the system down:
+/// stop the GPS control and its child processes, stop the web server and optionally power off the host.
+///  - requests: shutdown requests from the API.
+///  - gps_control: GPS control actor owning the child processes.
+///  - server: handle used to stop the web server.
+///  - power_off_on_shutdown: always power off the host on an API request, even if it didn't ask for it. Signals
+///    never power off the host, so stopping or restarting the service with systemd leaves it running.
+pub async fn shutdown_handler(mut requests: mpsc::Receiver<ShutdownRequest>,
+                              gps_control: Addr<GPSControl>,
+                              server: ServerHandle,
+                              power_off_on_shutdown: bool) {
+    //Without SIGTERM the API requests and SIGINT are still handled.
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => Some(terminate),
+        Err(e) => {
+            log::error!("Failed to listen for SIGTERM: {}", e);
+            None
+        }
+    };
+    let terminated = async {
+        match terminate.as_mut() {
+            Some(terminate) => terminate.recv().await,
+            None => std::future::pending().await,
+        }
+    };
+
+    let request = tokio::select! {
+        request = requests.recv() => match request {
+            Some(request) => ShutdownRequest { power_off: request.power_off || power_off_on_shutdown },
+            None => ShutdownRequest { power_off: false },
+        },
+        _ = terminated => {
+            log::info!("Received SIGTERM.");
+            ShutdownRequest { power_off: false }
+        },
+        _ = tokio::signal::ctrl_c() => {
+            log::info!("Received SIGINT.");
+            ShutdownRequest { power_off: false }
+        },
+    };
+    log::info!("Shutting down: {:?}", request);
+
+    if let Err(e) = gps_control.send(GPSMode::Stopped).await {
+        log::error!("Failed to stop the GPS service cleanly: {}", e);
+    }
+
+    log::info!("Stopping the web server.");
+    server.stop(true).await;
+    log::logger().flush();
+
+    if request.power_off {
+        power_off_host();
+    }
+}
+
+fn power_off_host() {
+    log::info!("Powering off the host.");
+    match Command::new("systemctl").arg("poweroff").status() {
+        Ok(status) if status.success() => (),
+        Ok(status) => log::error!("Power off failed with {}.", status),
+        Err(e) => log::error!("Failed to run systemctl poweroff: {}", e),
+    }
+    log::logger().flush();
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::gps_interface::gps_control::ControlStatus;
+use crate::settings::SettingsStatus;
+use crate::supervisor::{ProcessInfo, ProcessState};
+use crate::web_socket::GPSStatus;
+
+/// When the software was started, used for the uptime.
+pub struct StartTime(pub Instant);
+
+/// State of a correction data link.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    /// Not used in the current mode.
+    Disabled,
+    Up,
+    Down,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Links {
+    pub ntrip: LinkState,
+    pub lora: LinkState,
+}
+
+/// Disk usage of the data directory, and the space left on its file system.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DiskUsage {
+    pub path: String,
+    pub used_bytes: u64,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// Health of the whole system, returned by /api/status.
+#[derive(Serialize, Debug, Clone)]
+pub struct SystemStatus {
+    pub version: &'static str,
+    pub uptime_seconds: u64,
+    pub settings: SettingsStatus,
+    pub gps: GPSStatus,
+    #[serde(flatten)]
+    pub control: ControlStatus,
+    pub processes: BTreeMap<String, ProcessInfo>,
+    pub links: Links,
+    pub disk: DiskUsage,
+}
+
+/// The NTRIP link is up while str2str is running in the modes that stream corrections.
+pub fn ntrip_link(mode: &str, str2str: Option<&ProcessState>) -> LinkState {
+    match (mode, str2str) {
+        ("RTKRover", Some(ProcessState::Running{..})) | ("RTKBase", Some(ProcessState::Running{..})) => LinkState::Up,
+        ("RTKRover", _) | ("RTKBase", _) => LinkState::Down,
+        _ => LinkState::Disabled,
+    }
+}
+
+/// Size of all the files in a directory and its sub directories.
+fn directory_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += directory_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Work out the disk usage of the data directory. Errors are logged and leave the sizes at 0.
+pub fn disk_usage(path: &str) -> DiskUsage {
+    let mut usage = DiskUsage { path: path.to_string(), ..Default::default() };
+    match directory_size(Path::new(path)) {
+        Ok(size) => usage.used_bytes = size,
+        Err(e) => log::error!("Failed to read the size of {}: {}", path, e),
+    }
+    match nix::sys::statvfs::statvfs(path) {
+        Ok(stats) => {
+            usage.total_bytes = stats.blocks() * stats.fragment_size();
+            usage.available_bytes = stats.blocks_available() * stats.fragment_size();
+        },
+        Err(e) => log::error!("Failed to read the file system usage of {}: {}", path, e),
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_ntrip_link () {
+        assert_eq! (ntrip_link("RTKRover", Some(&ProcessState::Running { pid: 10 })), LinkState::Up);
+        assert_eq! (ntrip_link("RTKBase", Some(&ProcessState::Exited { code: Some(1) })), LinkState::Down);
+        assert_eq! (ntrip_link("RTKBase", Some(&ProcessState::Stopped)), LinkState::Down);
+        assert_eq! (ntrip_link("PPPMode", Some(&ProcessState::Stopped)), LinkState::Disabled);
+    }
+}
@@ -4,11 +4,16 @@ use actix::prelude::*;
 use actix::Message;
 use actix_web::{ web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use crate::geodesy::coordinates::{Ecef, Llh};
+use crate::geodesy::projection::{self, Enu, Utm};
 use crate::gps_interface::gps_control::GPSControl;
-use crate::gps_interface::gps_interface::GPSData;
+use crate::gps_interface::gps_interface::{FixQuality, GPSConnectionState, GPSData, SkyData};
+use crate::gps_interface::pps::TimeData;
+use crate::gps_interface::survey::SurveyStatus;
 use crate::settings::SettingsHandler;
 
 /// How often heartbeat pings are sent
@@ -40,12 +45,163 @@ pub struct GPSEvent {
 }
 
 
+/// Satellite view, sent by the GPS interface for every SKY report and passed on to the web sockets.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct SkyEvent {
+    pub data: SkyData,
+}
+
+/// Asks the monitor for the last satellite view of a device (the first one if None), for /api/sky.
+/// None is returned for an unknown device.
+#[derive(Message)]
+#[rtype(result = "Option<SkyData>")]
+pub struct GetSky {
+    pub device: Option<String>,
+}
+
+/// PPS timing quality, sent by the GPS interface for every pulse and when the pulses stop.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct TimeEvent {
+    pub data: TimeData,
+}
+
+/// Asks the monitor for the PPS timing quality, for /api/time.
+#[derive(Message)]
+#[rtype(result = "TimeData")]
+pub struct GetTime;
+
+/// Survey-in progress, polled by the GPS control (or read by the serial position source) in base station mode.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct SurveyEvent {
+    pub data: SurveyStatus,
+}
+
+/// Sent to the web sockets when the survey-in completes.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+struct SurveyCompleteEvent {
+    data: SurveyStatus,
+}
 
-/// do websocket handshake and start `MyWebSocket` actor
-pub async fn ws_index(r: HttpRequest, stream: web::Payload, data: web::Data<(Addr<GPSWebSocketMonitor>, Addr<GPSControl>, Addr<SettingsHandler>)>,) -> Result<HttpResponse, Error> {
+/// Asks the monitor for the last survey-in progress, for /api/base/survey. None if no survey has been reported.
+#[derive(Message)]
+#[rtype(result = "Option<SurveyStatus>")]
+pub struct GetSurvey;
+
+/// Position of the base station, sent by the GPS control for a fixed base (None when it isn't one) and worked out
+/// by the monitor when a survey-in completes. It is passed on to the web sockets, as the origin of their local ENU.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct BasePositionEvent {
+    pub position: Option<Ecef>,
+}
+
+/// Sent by the GPS interface when the connection to gpsd or the receiver changes, and passed on to the web sockets.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct GPSConnectionEvent {
+    pub state: GPSConnectionState,
+}
+
+/// Asks the monitor for the last known gpsd connection state, for /api/status.
+#[derive(Message)]
+#[rtype(result = "GPSStatus")]
+pub struct GetGPSStatus;
+
+/// Asks the monitor for the receivers reported by gpsd, for /api/devices.
+#[derive(Message)]
+#[rtype(result = "Vec<DeviceSummary>")]
+pub struct GetDevices;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceSummary {
+    pub path: String,
+    pub driver: String,
+    pub receiver_active: bool,
+    /// Fix of the last data from the device, None if nothing has been received from it yet.
+    pub fix: Option<FixQuality>,
+    pub stale: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GPSStatus {
+    #[serde(flatten)]
+    pub connection: GPSConnectionState,
+    /// Seconds since the last data from gpsd, None if nothing has been received yet.
+    pub seconds_since_data: Option<f64>,
+}
+
+/// Messages sent to the web socket clients besides the GPS data, tagged with their type
+/// (for example {"type": "connection", "gpsd_connected": false, ...}).
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage<'a> {
+    Connection(&'a GPSConnectionState),
+    Sky(&'a SkyData),
+    Time(&'a TimeData),
+    Survey(&'a SurveyStatus),
+    /// The survey-in has met its duration and accuracy, and the base position is fixed.
+    SurveyComplete(&'a SurveyStatus),
+}
+
+/// Commands sent by the web socket clients, for example {"type": "subscribe", "device": "/dev/ttyACM1"}.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientCommand {
+    /// Only send the GPS data and satellites of this device, or of all devices with null.
+    Subscribe { device: Option<String> },
+    /// Add projected coordinates to the GPS data, for example {"type": "projection", "utm": true, "enu_origin": {"type": "base"}}.
+    Projection(ProjectionOptions),
+}
+
+/// Projected coordinates added to the GPS data sent to a client. Nothing is added by default.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+struct ProjectionOptions {
+    /// Add UTM coordinates, in the zone of the position or in utm_zone when it is set.
+    #[serde(default)]
+    utm: bool,
+    #[serde(default)]
+    utm_zone: Option<u8>,
+    /// Add local east/north/up offsets from this origin.
+    #[serde(default)]
+    enu_origin: Option<EnuOrigin>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum EnuOrigin {
+    /// The base station position, while it is known.
+    Base,
+    /// A point given by its latitude, longitude and ellipsoidal height.
+    Point(Llh),
+}
+
+/// GPS data with the projections the client asked for.
+#[derive(Serialize)]
+struct ProjectedData<'a> {
+    #[serde(flatten)]
+    data: &'a GPSData,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    utm: Option<Utm>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enu: Option<Enu>,
+}
+
+#[derive(Deserialize)]
+pub struct SubscribeOptions {
+    device: Option<String>,
+}
+
+/// do websocket handshake and start `MyWebSocket` actor. With ?device=<path> only that device's data is sent.
+pub async fn ws_index(r: HttpRequest, stream: web::Payload, data: web::Data<(Addr<GPSWebSocketMonitor>, Addr<GPSControl>, Addr<SettingsHandler>)>,
+                      options: web::Query<SubscribeOptions>) -> Result<HttpResponse, Error> {
     log::info!("Performing web socket handshake: {:?}", r);
     let uuid = Uuid::new_v4();
-    let (addr, res) = ws::WsResponseBuilder::new(GPSWebSocket::new(&uuid, &data.get_ref().0), &r, stream).start_with_addr()?;
+    let socket = GPSWebSocket::new(&uuid, &data.get_ref().0, options.into_inner().device);
+    let (addr, res) = ws::WsResponseBuilder::new(socket, &r, stream).start_with_addr()?;
 
     (&data.get_ref().0).do_send(RegisterGPSWebSocketClient { uuid: uuid, addr: addr });
 
@@ -62,6 +218,11 @@ struct GPSWebSocket {
     monitor_address: Addr<GPSWebSocketMonitor>,
     /// heartbeat
     hb: Instant,
+    /// Device the client subscribed to, None for all of them.
+    device: Option<String>,
+    projection: ProjectionOptions,
+    /// Last base station position from the monitor.
+    base: Option<Ecef>,
 }
 
 impl Actor for GPSWebSocket {
@@ -89,7 +250,22 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GPSWebSocket {
             Ok(ws::Message::Pong(_)) => {
                 self.hb = Instant::now();
             }
-            Ok(ws::Message::Text(_)) => {},//No reason to respond
+            Ok(ws::Message::Text(text)) => {
+                match serde_json::from_str::<ClientCommand>(&text) {
+                    Ok(ClientCommand::Subscribe { device }) => {
+                        log::info!("Web socket client subscribed to {}", device.as_deref().unwrap_or("all devices"));
+                        self.device = device;
+                    },
+                    Ok(ClientCommand::Projection(options)) => {
+                        log::info!("Web socket client changed its projections: {:?}", options);
+                        match options.utm_zone {
+                            Some(zone) if !(1..=60).contains(&zone) => log::warn!("Invalid UTM zone {}, ignoring the projections.", zone),
+                            _ => self.projection = options,
+                        }
+                    },
+                    Err(e) => log::warn!("Unknown web socket command {}: {}", text, e),
+                }
+            },
             Ok(ws::Message::Binary(_)) => {},//ditto
             Ok(ws::Message::Close(reason)) => {
                 ctx.close(reason);
@@ -105,8 +281,39 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GPSWebSocket {
 }
 
 impl GPSWebSocket {
-    fn new(uuid: &Uuid, monitor_address: &Addr<GPSWebSocketMonitor>) -> Self {
-        Self { uuid: uuid.clone(), monitor_address: monitor_address.clone(), hb: Instant::now()}
+    fn send_message(&self, message: &ClientMessage, ctx: &mut <Self as Actor>::Context) {
+        match serde_json::to_string(message) {
+            Ok(message) => ctx.text(message),
+            Err(e) => log::error!("Failed to convert the web socket message to json: {}", e),
+        };
+    }
+
+    fn new(uuid: &Uuid, monitor_address: &Addr<GPSWebSocketMonitor>, device: Option<String>) -> Self {
+        Self { uuid: uuid.clone(), monitor_address: monitor_address.clone(), hb: Instant::now(), device: device,
+               projection: ProjectionOptions::default(), base: None }
+    }
+
+    /// The GPS data with the UTM and ENU coordinates the client asked for.
+    fn project<'a>(&self, data: &'a GPSData) -> ProjectedData<'a> {
+        let utm = match (self.projection.utm || self.projection.utm_zone.is_some(), data.llh()) {
+            (true, Some(llh)) => Some(projection::to_utm(&llh, self.projection.utm_zone)),
+            _ => None,
+        };
+        let origin = match &self.projection.enu_origin {
+            Some(EnuOrigin::Base) => self.base,
+            Some(EnuOrigin::Point(llh)) => Some(llh.to_ecef()),
+            None => None,
+        };
+        let enu = match (origin, data.ecef()) {
+            (Some(origin), Some(position)) => Some(projection::to_enu(&position, &origin)),
+            _ => None,
+        };
+        ProjectedData { data: data, utm: utm, enu: enu }
+    }
+
+    /// Whether the client wants the data of this device.
+    fn subscribed(&self, device: &str) -> bool {
+        self.device.as_deref().is_none_or(|subscribed| subscribed == device)
     }
 
     /// helper method that sends ping to client every second.
@@ -137,7 +344,10 @@ impl Handler<GPSEvent> for GPSWebSocket {
     type Result = ();
 
     fn handle(&mut self, msg: GPSEvent, ctx: &mut Self::Context) {
-        match serde_json::to_string(&msg.data) {
+        if !self.subscribed(msg.data.device()) {
+            return;
+        }
+        match serde_json::to_string(&self.project(&msg.data)) {
             Ok(gps_data) => ctx.text(gps_data),
             Err(e) => log::error!("Failed to parse GPS data to json: {}", e)
         };
@@ -145,15 +355,83 @@ impl Handler<GPSEvent> for GPSWebSocket {
 }
 
 
+impl Handler<SkyEvent> for GPSWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: SkyEvent, ctx: &mut Self::Context) {
+        if !self.subscribed(&msg.data.device) {
+            return;
+        }
+        self.send_message(&ClientMessage::Sky(&msg.data), ctx);
+    }
+}
+
+impl Handler<TimeEvent> for GPSWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: TimeEvent, ctx: &mut Self::Context) {
+        self.send_message(&ClientMessage::Time(&msg.data), ctx);
+    }
+}
+
+impl Handler<SurveyEvent> for GPSWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: SurveyEvent, ctx: &mut Self::Context) {
+        self.send_message(&ClientMessage::Survey(&msg.data), ctx);
+    }
+}
+
+impl Handler<SurveyCompleteEvent> for GPSWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: SurveyCompleteEvent, ctx: &mut Self::Context) {
+        self.send_message(&ClientMessage::SurveyComplete(&msg.data), ctx);
+    }
+}
+
+impl Handler<BasePositionEvent> for GPSWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: BasePositionEvent, _ctx: &mut Self::Context) {
+        self.base = msg.position;
+    }
+}
+
+impl Handler<GPSConnectionEvent> for GPSWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: GPSConnectionEvent, ctx: &mut Self::Context) {
+        self.send_message(&ClientMessage::Connection(&msg.state), ctx);
+    }
+}
+
+
 ///This structure keeps track of new web sockets and allows the GPS process to send data to running websockets.
 pub struct GPSWebSocketMonitor {
     listeners: HashMap<Uuid, Addr<GPSWebSocket>>,
+    connection: GPSConnectionState,
+    last_data: Option<Instant>,
+    /// Last data and satellite view of each device.
+    data: BTreeMap<String, GPSData>,
+    sky: BTreeMap<String, SkyData>,
+    /// Last timing report and when it was received.
+    time: (TimeData, Instant),
+    survey: Option<SurveyStatus>,
+    base: Option<Ecef>,
 }
 
 impl GPSWebSocketMonitor {
     pub fn new() -> Self {
         GPSWebSocketMonitor {
-            listeners: HashMap::new()
+            listeners: HashMap::new(),
+            connection: GPSConnectionState::default(),
+            last_data: None,
+            data: BTreeMap::new(),
+            sky: BTreeMap::new(),
+            time: (TimeData::default(), Instant::now()),
+            survey: None,
+            base: None,
         }
     }
 }
@@ -170,6 +448,9 @@ impl Handler<RegisterGPSWebSocketClient> for GPSWebSocketMonitor {
     type Result = ();
 
     fn handle(&mut self, msg: RegisterGPSWebSocketClient, _: &mut Self::Context) {
+        //Let the new client know straight away whether gpsd is connected.
+        msg.addr.do_send(GPSConnectionEvent { state: self.connection.clone() });
+        msg.addr.do_send(BasePositionEvent { position: self.base });
         self.listeners.insert(msg.uuid, msg.addr);
     }
 }
@@ -186,8 +467,163 @@ impl Handler<GPSEvent> for GPSWebSocketMonitor {
     type Result = ();
 
     fn handle(&mut self, msg: GPSEvent, _: &mut Context<Self>) {
-        for (_, addr) in &self.listeners {
+        self.last_data = Some(Instant::now());
+        for addr in self.listeners.values() {
+            addr.do_send(msg.clone());
+        }
+        self.data.insert(msg.data.device().to_string(), msg.data);
+    }
+}
+
+impl Handler<GPSConnectionEvent> for GPSWebSocketMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: GPSConnectionEvent, _: &mut Context<Self>) {
+        log::info!("GPS connection state changed: {:?}", msg.state);
+        for addr in self.listeners.values() {
+            addr.do_send(msg.clone());
+        }
+        self.connection = msg.state;
+    }
+}
+
+impl Handler<GetGPSStatus> for GPSWebSocketMonitor {
+    type Result = MessageResult<GetGPSStatus>;
+
+    fn handle(&mut self, _msg: GetGPSStatus, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(GPSStatus {
+            connection: self.connection.clone(),
+            seconds_since_data: self.last_data.map(|time| time.elapsed().as_secs_f64()),
+        })
+    }
+}
+
+impl Handler<SkyEvent> for GPSWebSocketMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SkyEvent, _: &mut Context<Self>) {
+        for addr in self.listeners.values() {
             addr.do_send(msg.clone());
         }
+        self.sky.insert(msg.data.device.clone(), msg.data);
+    }
+}
+
+impl Handler<GetSky> for GPSWebSocketMonitor {
+    type Result = Option<SkyData>;
+
+    fn handle(&mut self, msg: GetSky, _: &mut Context<Self>) -> Self::Result {
+        match msg.device {
+            Some(device) => self.sky.get(&device).cloned(),
+            None => Some(self.sky.values().next().cloned().unwrap_or_default()),
+        }
+    }
+}
+
+impl Handler<GetDevices> for GPSWebSocketMonitor {
+    type Result = MessageResult<GetDevices>;
+
+    fn handle(&mut self, _msg: GetDevices, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.connection.devices.iter().map(|device| {
+            let data = self.data.get(&device.path);
+            DeviceSummary {
+                path: device.path.clone(),
+                driver: device.driver.clone(),
+                receiver_active: device.receiver_active,
+                fix: data.map(|data| data.fix()),
+                stale: data.is_none_or(|data| data.is_stale()),
+            }
+        }).collect())
+    }
+}
+
+impl Handler<TimeEvent> for GPSWebSocketMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: TimeEvent, _: &mut Context<Self>) {
+        for addr in self.listeners.values() {
+            addr.do_send(msg.clone());
+        }
+        self.time = (msg.data, Instant::now());
+    }
+}
+
+impl Handler<SurveyEvent> for GPSWebSocketMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SurveyEvent, ctx: &mut Context<Self>) {
+        let was_valid = self.survey.as_ref().is_some_and(|survey| survey.valid);
+        for addr in self.listeners.values() {
+            addr.do_send(msg.clone());
+        }
+        if msg.data.valid && !was_valid {
+            log::info!("Survey-in complete after {} s, position accurate to {:.3} m.", msg.data.duration_seconds, msg.data.accuracy);
+            for addr in self.listeners.values() {
+                addr.do_send(SurveyCompleteEvent { data: msg.data.clone() });
+            }
+            let base = Ecef { x: msg.data.mean_ecef_x, y: msg.data.mean_ecef_y, z: msg.data.mean_ecef_z };
+            ctx.notify(BasePositionEvent { position: Some(base) });
+        }
+        self.survey = Some(msg.data);
+    }
+}
+
+impl Handler<BasePositionEvent> for GPSWebSocketMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: BasePositionEvent, _: &mut Context<Self>) {
+        for addr in self.listeners.values() {
+            addr.do_send(msg.clone());
+        }
+        self.base = msg.position;
+    }
+}
+
+impl Handler<GetSurvey> for GPSWebSocketMonitor {
+    type Result = Option<SurveyStatus>;
+
+    fn handle(&mut self, _msg: GetSurvey, _: &mut Context<Self>) -> Self::Result {
+        self.survey.clone()
+    }
+}
+
+impl Handler<GetTime> for GPSWebSocketMonitor {
+    type Result = MessageResult<GetTime>;
+
+    fn handle(&mut self, _msg: GetTime, _: &mut Context<Self>) -> Self::Result {
+        let (time, received) = &self.time;
+        let mut time = time.clone();
+        time.seconds_since_pulse = time.seconds_since_pulse.map(|seconds| seconds + received.elapsed().as_secs_f64());
+        MessageResult(time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_subscribe_command () {
+        let command: ClientCommand = serde_json::from_str(r#"{"type": "subscribe", "device": "/dev/ttyACM1"}"#).unwrap();
+        assert_eq! (command, ClientCommand::Subscribe { device: Some("/dev/ttyACM1".to_string()) });
+        let command: ClientCommand = serde_json::from_str(r#"{"type": "subscribe", "device": null}"#).unwrap();
+        assert_eq! (command, ClientCommand::Subscribe { device: None });
+        assert! (serde_json::from_str::<ClientCommand>(r#"{"type": "unsubscribe"}"#).is_err());
+    }
+
+    #[test]
+    fn test_projection_command () {
+        let command: ClientCommand = serde_json::from_str(r#"{"type": "projection", "utm": true, "enu_origin": {"type": "base"}}"#).unwrap();
+        assert_eq! (command, ClientCommand::Projection(ProjectionOptions { utm: true, utm_zone: None, enu_origin: Some(EnuOrigin::Base) }));
+        let command: ClientCommand = serde_json::from_str(
+            r#"{"type": "projection", "utm_zone": 18, "enu_origin": {"type": "point", "latitude": 45.0, "longitude": -75.0, "height": 100.0}}"#).unwrap();
+        assert_eq! (command, ClientCommand::Projection(ProjectionOptions {
+            utm: false,
+            utm_zone: Some(18),
+            enu_origin: Some(EnuOrigin::Point(Llh { latitude: 45.0, longitude: -75.0, height: 100.0 })),
+        }));
+        let command: ClientCommand = serde_json::from_str(r#"{"type": "projection"}"#).unwrap();
+        assert_eq! (command, ClientCommand::Projection(ProjectionOptions::default()));
     }
 }
@@ -1,3 +1,14 @@
 #!/bin/bash
 cd ..
-cargo run --release -- --start rtk-base --mount-point ${NTRIP_MOUNT_POINT} --username ${NTRIP_USERNAME} --server ${NTRIP_SERVER} --password ${NTRIP_PASSWORD} # --fixed_ECEF_x ${ECEF_X} --fixed_ECEF_Y ${ECEF_Y} --fixed_ECEF_Z ${ECEF_Z} --fixed_ECEF_accuracy ${ECEF_ACC}
\ No newline at end of file
+# The values set here replace those of the stored base mode, empty ones are left as stored. With none set, a different
+# mode stored through the web API is kept.
+# NTRIP_* settings are read from the environment, as is GPS_SITE to use a saved surveyed position.
+# A fixed position can also be given directly, in cm with ECEF_X, ECEF_Y and ECEF_Z or in degrees and m with
+# FIXED_LAT, FIXED_LON and FIXED_HEIGHT, with its accuracy in cm in ECEF_ACC.
+FIXED_POSITION=()
+if [ -n "${ECEF_X}" ]; then
+    FIXED_POSITION=(--fixed-ecef-x "${ECEF_X}" --fixed-ecef-y "${ECEF_Y}" --fixed-ecef-z "${ECEF_Z}" --fixed-position-accuracy "${ECEF_ACC}")
+elif [ -n "${FIXED_LAT}" ]; then
+    FIXED_POSITION=(--fixed-latitude "${FIXED_LAT}" --fixed-longitude "${FIXED_LON}" --fixed-height "${FIXED_HEIGHT}" --fixed-position-accuracy "${ECEF_ACC}")
+fi
+cargo run --release -- --start rtk-base "${FIXED_POSITION[@]}"
@@ -1,3 +1,5 @@
 #!/bin/bash
 cd ..
-cargo run --release -- --start rtk-rover --mount-point ${NTRIP_MOUNT_POINT} --username ${NTRIP_USERNAME} --server ${NTRIP_SERVER}
+# NTRIP_* settings are read from the environment and replace those of the stored rover mode, empty ones are left as
+# stored. With none set, a different mode stored through the web API is kept.
+cargo run --release -- --start rtk-rover
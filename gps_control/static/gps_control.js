@@ -44,10 +44,23 @@ async function build_page() {
     //container.appendChild(rtk_div);
 
     let shutdown_link = document.createElement("A");
-    shutdown_link.href = api.shutdown;
+    shutdown_link.href = "#";
+    shutdown_link.addEventListener('click', function (event) {
+        event.preventDefault();
+        api.shutdown();
+    });
     shutdown_link.innerHTML = "Shutdown";
     container.appendChild(shutdown_link);
 
+    let power_off_link = document.createElement("A");
+    power_off_link.href = "#";
+    power_off_link.addEventListener('click', function (event) {
+        event.preventDefault();
+        api.shutdown(true);
+    });
+    power_off_link.innerHTML = "Power Off";
+    container.appendChild(power_off_link);
+
     //Setup the GPS data socket stream
     let socket = api.subscribe();
     //console.log(socket);
@@ -66,8 +79,14 @@ async function build_page() {
     socket.addEventListener('message', function (event) {
         var msg = JSON.parse(event.data);
         //console.log(msg);
-        if (msg.mode) {
-            document.getElementById("gps_fix_id").innerHTML = msg.mode;
+        if (msg.type) {
+            if (msg.type === "connection" && !msg.gpsd_connected) {
+                document.getElementById("gps_fix_id").innerHTML = "No connection to gpsd";
+            }
+            return;
+        }
+        if (msg.fix) {
+            document.getElementById("gps_fix_id").innerHTML = msg.stale ? msg.fix + " (stale)" : msg.fix;
         }
         if (msg.time) {
             document.getElementById("time_id").innerHTML = msg.time;
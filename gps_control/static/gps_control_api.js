@@ -40,10 +40,24 @@ function api_instance() {
         return true;
     }
 
+    /**
+     * @brief Shut the system down, optionally powering off the host.
+     * @return true if the request was accepted.
+     */
+    async function shutdown(power_off=false) {
+        try {
+            let response = await fetch(requests.shutdown + "?power_off=" + power_off, {method: "POST", credentials: 'same-origin'});
+            return response.ok;
+        } catch (e) {
+            console.log(e);
+            return false;
+        }
+    }
+
     return {
         subscribe: subscribe,
         start_rtk: start_rtk,
-        shutdown: requests.shutdown,
+        shutdown: shutdown,
     };
 
     /**